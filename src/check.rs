@@ -0,0 +1,468 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    path::PathBuf,
+};
+
+use crate::config;
+use crate::fs::Fs;
+use crate::hooks::{self, HookSet};
+use crate::install::{self, FileDesc, InstallDesc};
+use crate::prelude::*;
+use crate::themes::{self, ThemeDesc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+pub struct Problem {
+    pub severity: Severity,
+    pub path: PathBuf,
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Error => write!(f, "error: ")?,
+            Severity::Warning => write!(f, "warning: ")?,
+        }
+
+        write!(f, "{}", self.path.display())?;
+        if let Some((line, column)) = self.position {
+            write!(f, ":{}:{}", line + 1, column + 1)?;
+        }
+
+        write!(f, ": {}", self.message)
+    }
+}
+
+/// Problems found while loading the manager directory.
+///
+/// Loading functions push everything they would otherwise skip over into a report,
+/// so that normal commands can log it and `check` can print it in full.
+#[derive(Debug, Default)]
+pub struct Report {
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    pub fn error(&mut self, path: impl Into<PathBuf>, message: impl fmt::Display) {
+        self.problems.push(Problem {
+            severity: Severity::Error,
+            path: path.into(),
+            position: None,
+            message: message.to_string(),
+        });
+    }
+
+    pub fn warning(&mut self, path: impl Into<PathBuf>, message: impl fmt::Display) {
+        self.problems.push(Problem {
+            severity: Severity::Warning,
+            path: path.into(),
+            position: None,
+            message: message.to_string(),
+        });
+    }
+
+    /// Records an error, extracting the line and column if it was caused by bad TOML
+    pub fn error_from(&mut self, path: impl Into<PathBuf>, error: &Error) {
+        let mut message = error.to_string();
+        let position = find_toml_error(error).and_then(|e| e.line_col());
+        if let Some((line, column)) = position {
+            let suffix = format!(" at line {} column {}", line + 1, column + 1);
+            if message.ends_with(&suffix) {
+                message.truncate(message.len() - suffix.len());
+            }
+        }
+
+        self.problems.push(Problem {
+            severity: Severity::Error,
+            path: path.into(),
+            position,
+            message,
+        });
+    }
+
+    pub fn num_errors(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Error)
+            .count()
+    }
+
+    pub fn num_warnings(&self) -> usize {
+        self.problems
+            .iter()
+            .filter(|problem| problem.severity == Severity::Warning)
+            .count()
+    }
+
+    pub fn log(&self) {
+        for problem in &self.problems {
+            match problem.severity {
+                Severity::Error => error!("{}", problem),
+                Severity::Warning => warn!("{}", problem),
+            }
+        }
+    }
+}
+
+fn find_toml_error(error: &Error) -> Option<&toml::de::Error> {
+    match error {
+        Error::Deserialize(e) => Some(e),
+        Error::Context { inner, .. } => find_toml_error(inner),
+        _ => None,
+    }
+}
+
+pub fn check_dirs(fs: &dyn Fs, dirs: &[PathBuf]) -> Report {
+    trace!("Checking {:?}", dirs);
+
+    let mut report = Report::default();
+
+    let configs = dirs
+        .iter()
+        .filter_map(|dir| match config::read_from(fs, dir) {
+            Ok(config) => Some((dir, config)),
+            Err(e) => {
+                report.error_from(dir.join("config.toml"), &e);
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let themes = themes::read_layers(fs, dirs, &mut report);
+    for (dir, config) in &configs {
+        for entry in config.schedule.iter().flatten() {
+            if !themes.contains_key(&entry.theme) {
+                report.error(
+                    dir.join("config.toml"),
+                    format!("Scheduled theme '{}' does not exist", entry.theme),
+                );
+            }
+        }
+    }
+    let mut global_hooks = hooks::read_layers(fs, dirs, &mut report);
+    check_themes(&themes, &mut report);
+
+    // Unlike `install::read_layers`, keep going after a broken layer to report all of them
+    let mut install: Option<InstallDesc> = None;
+    let mut found = false;
+    for dir in dirs {
+        let install_dir = dir.join("install");
+        if !fs.exists(&install_dir.join("install.toml")) {
+            continue;
+        }
+        found = true;
+
+        match install::read_from(fs, &install_dir) {
+            Ok(layer) => match install {
+                Some(ref mut install) => install.extend(layer),
+                None => install = Some(layer),
+            },
+            Err(e) => report.error_from(install_dir.join("install.toml"), &e),
+        }
+    }
+
+    match install {
+        Some(install) => {
+            global_hooks.add_inline(&install.hooks);
+            check_install(fs, &install, &themes, &global_hooks, &mut report);
+            check_usage(fs, &install, &themes, &mut report);
+        }
+        None if !found => {
+            if let Some(dir) = dirs.last() {
+                report.error(dir, Error::NoInstallFile);
+            }
+        }
+        None => {}
+    }
+
+    report.problems.sort_by(|a, b| a.path.cmp(&b.path));
+    report
+}
+
+fn check_themes(themes: &HashMap<String, ThemeDesc>, report: &mut Report) {
+    for theme in themes.values() {
+        if let Err(e) = themes::resolve_chain(themes, &theme.name) {
+            report.error(theme.dir.join("theme.toml"), e);
+        }
+
+        if let Some(ref counterpart) = theme.options.counterpart {
+            if !themes.contains_key(counterpart) {
+                report.error(
+                    theme.dir.join("theme.toml"),
+                    format!("Counterpart '{}' does not exist", counterpart),
+                );
+            }
+        }
+    }
+}
+
+fn check_install(
+    fs: &dyn Fs,
+    install: &InstallDesc,
+    themes: &HashMap<String, ThemeDesc>,
+    global_hooks: &HookSet,
+    report: &mut Report,
+) {
+    for file in &install.files {
+        let install_toml = file.dir.join("install.toml");
+
+        let sources = file_sources(fs, themes, file);
+
+        if sources.is_empty() {
+            report.error(
+                &install_toml,
+                format!(
+                    "File '{}' ({:?}) does not exist in the install directory or any theme",
+                    file.name, file.path
+                ),
+            );
+        }
+
+        if file.template {
+            for source in sources {
+                let res = fs
+                    .read_to_string(&source)
+                    .context("Failed to read template file")
+                    .and_then(|template| {
+                        mustache::compile_str(&template)
+                            .context("Failed to compile mustache template")
+                    });
+                if let Err(e) = res {
+                    report.error_from(source, &e);
+                }
+            }
+        }
+
+        for name in &file.on_change_hooks {
+            let exists = global_hooks.onchange.find(name).is_some()
+                || themes
+                    .values()
+                    .any(|theme| theme.hooks.onchange.find(name).is_some());
+            if !exists {
+                report.error(
+                    &install_toml,
+                    format!(
+                        "File '{}' references onchange hook '{}' that does not exist",
+                        file.name, name
+                    ),
+                );
+            }
+        }
+
+        if let Err(e) = mustache::compile_str(&file.target) {
+            report.error(
+                &install_toml,
+                format!(
+                    "Target of file '{}' is not a valid template: {}",
+                    file.name, e
+                ),
+            );
+        }
+    }
+}
+
+/// Cross-references units and values provided by themes with the files and templates using them
+pub fn check_usage(
+    fs: &dyn Fs,
+    install: &InstallDesc,
+    themes: &HashMap<String, ThemeDesc>,
+    report: &mut Report,
+) {
+    let mut file_variables = HashMap::<&str, BTreeSet<String>>::new();
+    for file in install.files.iter().filter(|file| file.template) {
+        let variables = file_variables.entry(&file.name).or_default();
+        for source in file_sources(fs, themes, file) {
+            if let Ok(template) = fs.read_to_string(&source) {
+                variables.extend(utils::template_variables(&template));
+            }
+        }
+    }
+
+    for theme in themes.values() {
+        for (unit_name, unit) in &theme.units {
+            if !install.files.iter().any(|file| file.uses_unit(unit_name)) {
+                report.warning(
+                    &theme.dir,
+                    format!("Unit '{}' is not used by any installed file", unit_name),
+                );
+                continue;
+            }
+
+            // Shared units like palettes are expected to provide more than any one template uses
+            let is_shared = !install.files.iter().any(|file| &file.name == unit_name);
+            let templates = install
+                .files
+                .iter()
+                .filter(|file| file.uses_unit(unit_name))
+                .filter_map(|file| file_variables.get(file.name.as_str()))
+                .collect::<Vec<_>>();
+            if is_shared || templates.is_empty() {
+                continue;
+            }
+
+            let variables = templates.into_iter().flatten().collect::<BTreeSet<_>>();
+            let mut orphaned = unit
+                .values
+                .keys()
+                .filter(|value| !variables.contains(value))
+                .collect::<Vec<_>>();
+            orphaned.sort();
+            for value in orphaned {
+                report.warning(
+                    &theme.dir,
+                    format!(
+                        "Value '{}' of unit '{}' is not used by any template",
+                        value, unit_name
+                    ),
+                );
+            }
+        }
+    }
+
+    for file in install.files.iter().filter(|file| file.template) {
+        for variable in &file_variables[file.name.as_str()] {
+            let provided = themes.values().any(|theme| {
+                file.unit_names()
+                    .filter_map(|unit_name| theme.units.get(unit_name))
+                    .any(|unit| unit.values.contains_key(variable))
+            });
+            if !provided {
+                report.warning(
+                    file.dir.join("install.toml"),
+                    format!(
+                        "Template of file '{}' references value '{}' that no theme provides",
+                        file.name, variable
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Every existing location a file can be installed from
+fn file_sources(fs: &dyn Fs, themes: &HashMap<String, ThemeDesc>, file: &FileDesc) -> Vec<PathBuf> {
+    themes
+        .values()
+        .flat_map(|theme| theme.dirs())
+        .map(|dir| dir.join(&file.path))
+        .chain(std::iter::once(file.dir.join(&file.path)))
+        .filter(|path| fs.exists(path))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+    use std::path::Path;
+
+    const INSTALL: &str = "[[files]]\npath = \"colors.conf\"\ntarget = \"/out/colors.conf\"\n";
+
+    /// Checks a manager directory with a single template using the `colors` unit
+    fn check(files: &[(&str, &str)]) -> Report {
+        let fs = MemoryFs::with_files([
+            ("/m/install/install.toml", INSTALL),
+            ("/m/install/colors.conf", "fg={{fg}}"),
+            ("/m/theme-default/unit-colors.toml", "fg = \"white\""),
+        ]);
+        for (path, contents) in files {
+            let path = Path::new(path);
+            fs.create_dir_all(path.parent().unwrap()).unwrap();
+            fs.write(path, contents.as_bytes()).unwrap();
+        }
+
+        check_dirs(&fs, &[PathBuf::from("/m")])
+    }
+
+    fn messages(report: &Report) -> Vec<String> {
+        report.problems.iter().map(|p| p.to_string()).collect()
+    }
+
+    #[test]
+    fn clean_directory() {
+        let report = check(&[]);
+        assert!(report.problems.is_empty(), "{:?}", messages(&report));
+    }
+
+    #[test]
+    fn bad_toml_has_position() {
+        let report = check(&[
+            ("/m/theme-dark/theme.toml", "name = \"dark\"\ninherits = "),
+            ("/m/theme-dark/unit-colors.toml", "fg = \"black\""),
+        ]);
+
+        assert_eq!(report.num_errors(), 1);
+        let problem = &report.problems[0];
+        assert_eq!(problem.path, Path::new("/m/theme-dark/theme.toml"));
+        assert_eq!(problem.position, Some((1, 11)));
+    }
+
+    #[test]
+    fn unknown_hook_set() {
+        let report = check(&[("/m/theme-dark/hook-bogus-reload", "true")]);
+
+        assert_eq!(
+            messages(&report),
+            ["warning: /m/theme-dark/hook-bogus-reload: Hook set 'bogus' is invalid. Hook will be ignored"]
+        );
+    }
+
+    #[test]
+    fn inheritance_cycle() {
+        let report = check(&[
+            ("/m/theme-a/theme.toml", "inherits = \"b\""),
+            ("/m/theme-b/theme.toml", "inherits = \"a\""),
+        ]);
+
+        assert_eq!(report.num_errors(), 2);
+        assert!(messages(&report)[0].ends_with("Inheritance cycle: a -> b -> a"));
+    }
+
+    #[test]
+    fn missing_file() {
+        let report = check(&[(
+            "/m/install/install.toml",
+            "[[files]]\npath = \"missing.conf\"\ntarget = \"/out/missing.conf\"\n",
+        )]);
+
+        assert_eq!(report.num_errors(), 1);
+        assert!(
+            messages(&report)[0].contains("does not exist in the install directory or any theme")
+        );
+    }
+
+    #[test]
+    fn broken_template() {
+        let report = check(&[("/m/install/colors.conf", "fg={{fg")]);
+
+        assert_eq!(report.num_errors(), 1);
+        assert_eq!(report.problems[0].path, Path::new("/m/install/colors.conf"));
+    }
+
+    #[test]
+    fn unused_unit_and_value() {
+        let report = check(&[
+            ("/m/theme-default/unit-unused.toml", "a = \"b\""),
+            (
+                "/m/theme-dark/unit-colors.toml",
+                "fg = \"black\"\nbg = \"gray\"",
+            ),
+        ]);
+
+        assert_eq!(
+            messages(&report),
+            [
+                "warning: /m/theme-dark: Value 'bg' of unit 'colors' is not used by any template",
+                "warning: /m/theme-default: Unit 'unused' is not used by any installed file",
+            ]
+        );
+    }
+}
@@ -0,0 +1,298 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use crate::prelude::*;
+
+/// How long to keep logging the captured output of a command after it exited
+const OUTPUT_GRACE: Duration = Duration::from_millis(100);
+
+/// A program to run along with its arguments, working directory and environment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandLine {
+    pub program: OsString,
+    pub args: Vec<OsString>,
+    pub cwd: Option<PathBuf>,
+    /// Set in addition to the environment of the theme manager
+    pub env: Vec<(String, String)>,
+}
+
+impl CommandLine {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        CommandLine {
+            program: program.as_ref().to_owned(),
+            args: Vec::new(),
+            cwd: None,
+            env: Vec::new(),
+        }
+    }
+
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.args.push(arg.as_ref().to_owned());
+        self
+    }
+
+    pub fn args<I>(mut self, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<OsStr>,
+    {
+        self.args
+            .extend(args.into_iter().map(|arg| arg.as_ref().to_owned()));
+        self
+    }
+
+    pub fn current_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    pub fn env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn envs<'a>(mut self, vars: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        self.env.extend(
+            vars.into_iter()
+                .map(|(name, value)| (String::from(name), String::from(value))),
+        );
+        self
+    }
+
+    fn to_command(&self) -> Command {
+        let mut command = Command::new(&self.program);
+        command.args(&self.args).envs(self.env.iter().cloned());
+        if let Some(ref cwd) = self.cwd {
+            command.current_dir(cwd);
+        }
+
+        command
+    }
+}
+
+/// How to run a command
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunOptions {
+    /// Killed after this long
+    pub timeout: Option<Duration>,
+    /// Log stdout and stderr with this prefix instead of passing them through
+    pub output_prefix: Option<String>,
+}
+
+/// Runs external programs, so that what would be run can be checked without running it
+pub trait CommandRunner: std::fmt::Debug + Send + Sync {
+    /// Runs the command to completion, describing why it failed if it did
+    fn run(&self, command: &CommandLine, options: &RunOptions) -> Result<(), String>;
+
+    /// Whether a process with exactly this name is running
+    fn is_running(&self, process: &str) -> bool;
+
+    /// Whether the program can be found in `PATH`
+    fn is_installed(&self, program: &str) -> bool;
+}
+
+/// Runs commands for real
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(&self, command: &CommandLine, options: &RunOptions) -> Result<(), String> {
+        run_command(
+            command.to_command(),
+            options.timeout,
+            options.output_prefix.as_deref(),
+        )
+    }
+
+    fn is_running(&self, process: &str) -> bool {
+        let entries = match std::fs::read_dir("/proc") {
+            Ok(entries) => entries,
+            Err(_) => return false,
+        };
+
+        entries.filter_map(Result::ok).any(|entry| {
+            std::fs::read_to_string(entry.path().join("comm"))
+                .is_ok_and(|comm| comm.trim_end() == process)
+        })
+    }
+
+    fn is_installed(&self, program: &str) -> bool {
+        std::env::var_os("PATH").is_some_and(|path| {
+            std::env::split_paths(&path).any(|dir| is_executable(&dir.join(program)))
+        })
+    }
+}
+
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// Runs a command and describes why it failed if it did.
+///
+/// With an output prefix, stdout and stderr are logged line by line instead of passed through.
+fn run_command(
+    mut command: Command,
+    timeout: Option<Duration>,
+    output_prefix: Option<&str>,
+) -> Result<(), String> {
+    if output_prefix.is_some() {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+    let mut handle = command
+        .spawn()
+        .map_err(|e| format!("failed to start: {}", e))?;
+
+    let (done_tx, done_rx) = mpsc::channel();
+    let mut readers = 0;
+    if let Some(prefix) = output_prefix {
+        let stdout = handle
+            .stdout
+            .take()
+            .map(|out| (Box::new(out) as Box<dyn Read + Send>, false));
+        let stderr = handle
+            .stderr
+            .take()
+            .map(|err| (Box::new(err) as Box<dyn Read + Send>, true));
+        for (stream, is_stderr) in stdout.into_iter().chain(stderr) {
+            let prefix = String::from(prefix);
+            let done_tx = done_tx.clone();
+            readers += 1;
+            std::thread::spawn(move || {
+                for line in BufReader::new(stream).lines().map_while(Result::ok) {
+                    if is_stderr {
+                        warn!("[{}] {}", prefix, line);
+                    } else {
+                        info!("[{}] {}", prefix, line);
+                    }
+                }
+                let _ = done_tx.send(());
+            });
+        }
+    }
+
+    let exit_status = wait_timeout(&mut handle, timeout).map_err(|e| e.to_string());
+
+    // Background processes started by the hook can keep the pipes open, so don't wait for
+    // them for long
+    let deadline = Instant::now() + OUTPUT_GRACE;
+    for _ in 0..readers {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if done_rx.recv_timeout(remaining).is_err() {
+            break;
+        }
+    }
+
+    let exit_status = match exit_status? {
+        Some(exit_status) => exit_status,
+        None => {
+            return Err(format!(
+                "timed out after {} seconds",
+                timeout.unwrap_or_default().as_secs()
+            ))
+        }
+    };
+
+    if exit_status.success() {
+        Ok(())
+    } else if let Some(code) = exit_status.code() {
+        Err(format!("finished with exit code {}", code))
+    } else {
+        Err(String::from("terminated by signal"))
+    }
+}
+
+/// Waits for the child to exit, killing it if it takes longer than the timeout
+fn wait_timeout(
+    child: &mut Child,
+    timeout: Option<Duration>,
+) -> Result<Option<ExitStatus>, std::io::Error> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return child.wait().map(Some),
+    };
+
+    let start = Instant::now();
+    loop {
+        if let Some(exit_status) = child.try_wait()? {
+            return Ok(Some(exit_status));
+        }
+
+        if start.elapsed() >= timeout {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+
+        std::thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Records commands instead of running them, for dry runs and tests
+#[derive(Debug, Default)]
+pub struct RecordingRunner {
+    pub calls: std::sync::Mutex<Vec<CommandLine>>,
+    /// Programs that are running
+    pub running: Vec<String>,
+    /// Programs that are installed
+    pub installed: Vec<String>,
+    /// Programs that fail when run
+    pub failing: Vec<String>,
+    /// Asked whether programs are running or installed instead of the lists above, so that a dry
+    /// run records the reload actions a real install would run
+    pub probe: Option<std::sync::Arc<dyn CommandRunner>>,
+}
+
+impl RecordingRunner {
+    /// The recorded commands as program and arguments
+    pub fn argv(&self) -> Vec<Vec<String>> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|command| {
+                std::iter::once(&command.program)
+                    .chain(&command.args)
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(&self, command: &CommandLine, _options: &RunOptions) -> Result<(), String> {
+        self.calls.lock().unwrap().push(command.clone());
+        if self
+            .failing
+            .iter()
+            .any(|program| command.program == **program)
+        {
+            Err(String::from("finished with exit code 1"))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn is_running(&self, process: &str) -> bool {
+        match self.probe {
+            Some(ref probe) => probe.is_running(process),
+            None => self.running.iter().any(|p| p == process),
+        }
+    }
+
+    fn is_installed(&self, program: &str) -> bool {
+        match self.probe {
+            Some(ref probe) => probe.is_installed(program),
+            None => self.installed.iter().any(|p| p == program),
+        }
+    }
+}
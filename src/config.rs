@@ -0,0 +1,326 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::Deserialize;
+
+use crate::fs::Fs;
+use crate::prelude::*;
+use crate::schedule::{Location, ScheduleEntry};
+
+/// What to do when a hook fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    Abort,
+    Warn,
+    Ignore,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "abort" => Ok(FailurePolicy::Abort),
+            "warn" => Ok(FailurePolicy::Warn),
+            "ignore" => Ok(FailurePolicy::Ignore),
+            _ => Err(Error::InvalidSetting(format!(
+                "Unknown failure policy '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Which versions of overwritten files to keep
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackupPolicy {
+    None,
+    /// Keep only what was there before the first install
+    Original,
+    /// Keep a copy from every install
+    Every,
+}
+
+impl FromStr for BackupPolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(BackupPolicy::None),
+            "original" => Ok(BackupPolicy::Original),
+            "every" => Ok(BackupPolicy::Every),
+            _ => Err(Error::InvalidSetting(format!(
+                "Unknown backup policy '{}'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Settings as they appear in `config.toml`, everything optional so that layers can be merged
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConfigFile {
+    pub default_theme: Option<String>,
+    pub strict: Option<bool>,
+    pub profile: Option<String>,
+    pub backup: Option<BackupPolicy>,
+    #[serde(default)]
+    pub hooks: HookConfigFile,
+    /// Settings for hooks with a given name, overriding `[hooks]`
+    #[serde(default)]
+    pub hook: HashMap<String, HookConfigFile>,
+    /// Replaces the schedule of earlier layers as a whole
+    pub schedule: Option<Vec<ScheduleEntry>>,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HookConfigFile {
+    /// Timeout in seconds
+    pub timeout: Option<u64>,
+    pub on_failure: Option<FailurePolicy>,
+    /// Log the output of hooks instead of passing it through
+    pub capture: Option<bool>,
+    /// Run hooks that don't depend on each other at the same time
+    pub parallel: Option<bool>,
+    /// Hooks of the same phase this one has to run before
+    pub before: Option<Vec<String>>,
+    /// Hooks of the same phase this one has to run after
+    pub after: Option<Vec<String>>,
+}
+
+impl HookConfigFile {
+    fn merge(&mut self, other: HookConfigFile) {
+        merge(&mut self.timeout, other.timeout);
+        merge(&mut self.on_failure, other.on_failure);
+        merge(&mut self.capture, other.capture);
+        merge(&mut self.parallel, other.parallel);
+        merge(&mut self.before, other.before);
+        merge(&mut self.after, other.after);
+    }
+}
+
+fn merge<T>(this: &mut Option<T>, other: Option<T>) {
+    if other.is_some() {
+        *this = other;
+    }
+}
+
+impl ConfigFile {
+    /// Overrides settings with the ones set in `other`
+    pub fn merge(&mut self, other: ConfigFile) {
+        merge(&mut self.default_theme, other.default_theme);
+        merge(&mut self.strict, other.strict);
+        merge(&mut self.profile, other.profile);
+        merge(&mut self.backup, other.backup);
+        self.hooks.merge(other.hooks);
+        for (name, hook) in other.hook {
+            self.hook.entry(name).or_default().merge(hook);
+        }
+        merge(&mut self.schedule, other.schedule);
+        merge(&mut self.location, other.location);
+    }
+
+    /// Like `merge`, but the hook settings also override the ones of named hooks
+    pub fn apply_overrides(&mut self, overrides: ConfigFile) {
+        for hook in self.hook.values_mut() {
+            hook.merge(overrides.hooks.clone());
+        }
+        self.merge(overrides);
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub default_theme: String,
+    pub strict: bool,
+    pub profile: Option<String>,
+    pub backup: BackupPolicy,
+    pub hooks: HookConfig,
+    /// Themes for `auto` to install depending on the time of day
+    pub schedule: Vec<ScheduleEntry>,
+    pub location: Option<Location>,
+}
+
+#[derive(Debug, Clone)]
+pub struct HookConfig {
+    pub timeout: Option<Duration>,
+    pub on_failure: FailurePolicy,
+    pub capture: bool,
+    pub parallel: bool,
+    pub before: Vec<String>,
+    pub after: Vec<String>,
+    /// Complete settings of hooks configured by name
+    pub named: HashMap<String, HookConfig>,
+}
+
+impl HookConfig {
+    /// The settings for the hook with the given name
+    pub fn for_hook(&self, name: &str) -> &HookConfig {
+        self.named.get(name).unwrap_or(self)
+    }
+
+    fn with_overrides(&self, file: HookConfigFile) -> HookConfig {
+        HookConfig {
+            timeout: file.timeout.map(Duration::from_secs).or(self.timeout),
+            on_failure: file.on_failure.unwrap_or(self.on_failure),
+            capture: file.capture.unwrap_or(self.capture),
+            parallel: file.parallel.unwrap_or(self.parallel),
+            before: file.before.unwrap_or_default(),
+            after: file.after.unwrap_or_default(),
+            named: HashMap::new(),
+        }
+    }
+}
+
+impl Default for HookConfig {
+    fn default() -> Self {
+        HookConfig {
+            timeout: None,
+            on_failure: FailurePolicy::Abort,
+            capture: false,
+            parallel: false,
+            before: Vec::new(),
+            after: Vec::new(),
+            named: HashMap::new(),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        ConfigFile::default().into()
+    }
+}
+
+impl From<ConfigFile> for Config {
+    fn from(file: ConfigFile) -> Self {
+        Config {
+            default_theme: file
+                .default_theme
+                .unwrap_or_else(|| String::from("default")),
+            strict: file.strict.unwrap_or(false),
+            profile: file.profile,
+            backup: file.backup.unwrap_or(BackupPolicy::None),
+            hooks: {
+                let mut hooks = HookConfig::default().with_overrides(file.hooks);
+                hooks.named = file
+                    .hook
+                    .into_iter()
+                    .map(|(name, hook)| {
+                        let named = hooks.with_overrides(hook);
+                        (name, named)
+                    })
+                    .collect();
+                hooks
+            },
+            schedule: file.schedule.unwrap_or_default(),
+            location: file.location,
+        }
+    }
+}
+
+pub fn read_from(fs: &dyn Fs, dir: &Path) -> Result<ConfigFile, Error> {
+    let path = dir.join("config.toml");
+    if !fs.exists(&path) {
+        return Ok(ConfigFile::default());
+    }
+
+    trace!("Reading config from {:?}", path);
+    let s = fs
+        .read_to_string(&path)
+        .context("Could not read config.toml")?;
+    let config = toml::de::from_str(&s).context("config.toml parse error")?;
+
+    Ok(config)
+}
+
+/// Reads `config.toml` from every layer, later layers overriding earlier ones
+pub fn read_layers(fs: &dyn Fs, dirs: &[PathBuf]) -> Result<ConfigFile, Error> {
+    let mut config = ConfigFile::default();
+    for dir in dirs {
+        config.merge(read_from(fs, dir)?);
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge_layers() {
+        let mut config: ConfigFile =
+            toml::de::from_str("default_theme = \"base\"\nstrict = true\n[hooks]\ntimeout = 5\n")
+                .unwrap();
+        let user: ConfigFile =
+            toml::de::from_str("strict = false\n[hooks]\non_failure = \"warn\"\n").unwrap();
+        config.merge(user);
+
+        let config = Config::from(config);
+        assert_eq!(config.default_theme, "base");
+        assert!(!config.strict);
+        assert_eq!(config.hooks.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(config.hooks.on_failure, FailurePolicy::Warn);
+        assert_eq!(config.backup, BackupPolicy::None);
+    }
+
+    #[test]
+    fn named_hooks_fall_back_to_defaults() {
+        let mut config: ConfigFile =
+            toml::de::from_str("[hooks]\ntimeout = 5\n[hook.reload]\non_failure = \"warn\"\n")
+                .unwrap();
+        let user: ConfigFile = toml::de::from_str("[hook.reload]\ncapture = true\n").unwrap();
+        config.merge(user);
+
+        let config = Config::from(config);
+        let reload = config.hooks.for_hook("reload");
+        assert_eq!(reload.timeout, Some(Duration::from_secs(5)));
+        assert_eq!(reload.on_failure, FailurePolicy::Warn);
+        assert!(reload.capture);
+        assert_eq!(
+            config.hooks.for_hook("other").on_failure,
+            FailurePolicy::Abort
+        );
+    }
+
+    #[test]
+    fn overrides_apply_to_named_hooks() {
+        let mut config: ConfigFile = toml::de::from_str(
+            "strict = true
+[hook.reload]
+timeout = 5
+",
+        )
+        .unwrap();
+        config.apply_overrides(ConfigFile {
+            strict: Some(false),
+            hooks: HookConfigFile {
+                timeout: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+
+        let config = Config::from(config);
+        assert!(!config.strict);
+        assert_eq!(
+            config.hooks.for_hook("reload").timeout,
+            Some(Duration::from_secs(1))
+        );
+    }
+
+    #[test]
+    fn unknown_setting() {
+        assert!(toml::de::from_str::<ConfigFile>("defualt_theme = \"x\"\n").is_err());
+    }
+}
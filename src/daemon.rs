@@ -0,0 +1,298 @@
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::config::ConfigFile;
+use crate::manager::ThemeManager;
+use crate::prelude::*;
+use crate::watch;
+
+/// How long a client may take to send a request or receive a reply
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A command sent to the daemon, one JSON object per line
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Install {
+        theme: String,
+    },
+    Current,
+    List,
+    Toggle,
+    GetValue {
+        /// The installed theme if not given
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        theme: Option<String>,
+        unit: String,
+        value: String,
+    },
+    /// Keeps the connection open to receive events
+    Subscribe,
+    /// Reads the manager directories again
+    Reload,
+    /// The manager directories, as absolute paths
+    Dirs,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reply {
+    Ok(Value),
+    Error(String),
+}
+
+/// Sent to subscribers, one JSON object per line
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event {
+    Installed { theme: String },
+}
+
+pub fn socket_path(state_dir: &Path) -> PathBuf {
+    state_dir.join("daemon.sock")
+}
+
+/// Runs a request against a loaded manager. `Subscribe` and `Reload` need a daemon.
+pub fn execute(manager: &ThemeManager, request: &Request) -> Result<Value, Error> {
+    match request {
+        Request::Install { theme } => {
+            manager.switch_to(theme)?;
+            Ok(Value::from(theme.as_str()))
+        }
+
+        Request::Current => Ok(manager.installed_theme()?.map_or(Value::Null, Value::from)),
+
+        Request::List => {
+            let mut names = manager.themes().keys().cloned().collect::<Vec<_>>();
+            names.sort();
+            Ok(Value::from(names))
+        }
+
+        Request::Toggle => Ok(Value::from(manager.toggle()?)),
+
+        Request::GetValue { theme, unit, value } => {
+            let theme = match theme {
+                Some(theme) => theme.clone(),
+                None => manager.installed_theme()?.ok_or(Error::NotInstalled)?,
+            };
+            let units = manager.resolve_units(&theme)?;
+            units
+                .get(unit)
+                .and_then(|values| values.get(value))
+                .map(|v| Value::from(v.as_str()))
+                .ok_or_else(|| Error::NoValue(unit.clone(), value.clone()))
+        }
+
+        Request::Dirs => Ok(serde_json::to_value(absolute_dirs(manager.dirs()))?),
+
+        Request::Subscribe | Request::Reload => Err(Error::NoDaemon),
+    }
+}
+
+/// Makes the directories comparable between processes started in different places
+pub fn absolute_dirs(dirs: &[PathBuf]) -> Vec<PathBuf> {
+    dirs.iter()
+        .map(|dir| std::path::absolute(dir).unwrap_or_else(|_| dir.clone()))
+        .collect()
+}
+
+struct Daemon {
+    manager: Mutex<ThemeManager>,
+    subscribers: Mutex<Vec<UnixStream>>,
+    dirs: Vec<PathBuf>,
+    state_dir: PathBuf,
+    overrides: ConfigFile,
+}
+
+impl Daemon {
+    fn serve(&self, stream: UnixStream) -> Result<(), Error> {
+        // A stuck client only holds up its own thread, but shouldn't keep it around forever
+        stream.set_read_timeout(Some(CLIENT_TIMEOUT))?;
+        stream.set_write_timeout(Some(CLIENT_TIMEOUT))?;
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            let request = match serde_json::from_str::<Request>(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    send(
+                        &mut writer,
+                        &Reply::Error(format!("Invalid request: {}", e)),
+                    )?;
+                    continue;
+                }
+            };
+            trace!("Received {:?}", request);
+
+            let res = match request {
+                Request::Subscribe => {
+                    send(&mut writer, &Reply::Ok(Value::Null))?;
+                    self.subscribers.lock().unwrap().push(writer);
+                    return Ok(());
+                }
+                Request::Reload => self.reload().map(|()| Value::Null),
+                ref request => execute(&self.manager.lock().unwrap(), request),
+            };
+
+            if let (Request::Install { .. } | Request::Toggle, Ok(theme)) = (&request, &res) {
+                self.broadcast(&Event::Installed {
+                    theme: theme.as_str().unwrap_or_default().to_owned(),
+                });
+            }
+
+            let reply = match res {
+                Ok(value) => Reply::Ok(value),
+                Err(e) => Reply::Error(e.to_string()),
+            };
+            send(&mut writer, &reply)?;
+        }
+
+        Ok(())
+    }
+
+    fn reload(&self) -> Result<(), Error> {
+        let manager =
+            ThemeManager::read_from_dirs(&self.dirs, &self.state_dir, self.overrides.clone())?;
+        *self.manager.lock().unwrap() = manager;
+
+        Ok(())
+    }
+
+    /// Polls the manager directories in the background and reloads whenever they change
+    fn watch(&self, mut files: BTreeMap<PathBuf, SystemTime>) -> ! {
+        loop {
+            let batch = watch::next_batch(&self.dirs, &mut files);
+            trace!("{} files changed, reloading", batch.len());
+            if let Err(e) = self.reload() {
+                error!("Could not reload: {}", e);
+            }
+        }
+    }
+
+    /// Sends an event to every subscriber, dropping the ones that went away
+    fn broadcast(&self, event: &Event) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain_mut(|subscriber| send(subscriber, event).is_ok());
+    }
+}
+
+fn send(stream: &mut UnixStream, message: &impl Serialize) -> Result<(), Error> {
+    let line = serde_json::to_string(message)?;
+    writeln!(stream, "{}", line)?;
+
+    Ok(())
+}
+
+/// Loads the manager once and serves requests on the socket in the state directory until killed
+pub fn run(dirs: &[PathBuf], state_dir: &Path, overrides: ConfigFile) -> Result<(), Error> {
+    let path = socket_path(state_dir);
+    if UnixStream::connect(&path).is_ok() {
+        return Err(Error::DaemonRunning(path));
+    }
+    if path.exists() {
+        trace!("Removing stale socket {:?}", path);
+        std::fs::remove_file(&path).context("Could not remove stale socket")?;
+    }
+
+    // Taken first so that changes made while reading are picked up by the watcher
+    let snapshot = watch::snapshot(dirs);
+    let daemon = Arc::new(Daemon {
+        manager: Mutex::new(ThemeManager::read_from_dirs(
+            dirs,
+            state_dir,
+            overrides.clone(),
+        )?),
+        subscribers: Mutex::new(Vec::new()),
+        dirs: dirs.to_owned(),
+        state_dir: state_dir.to_owned(),
+        overrides,
+    });
+
+    std::fs::create_dir_all(state_dir).context("Could not create state directory")?;
+    let listener = UnixListener::bind(&path).context("Could not create socket")?;
+    println!("Listening on {}", path.display());
+
+    let watcher = Arc::clone(&daemon);
+    std::thread::spawn(move || watcher.watch(snapshot));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let daemon = Arc::clone(&daemon);
+                std::thread::spawn(move || {
+                    if let Err(e) = daemon.serve(stream) {
+                        warn!("Connection failed: {}", e);
+                    }
+                });
+            }
+            Err(e) => warn!("Could not accept connection: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+/// Connection to a running daemon
+pub struct Client {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl Client {
+    /// Connects to the daemon if one is listening
+    pub fn connect(state_dir: &Path) -> Option<Client> {
+        let stream = UnixStream::connect(socket_path(state_dir)).ok()?;
+        let writer = stream.try_clone().ok()?;
+
+        Some(Client {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    /// Whether the daemon serves exactly these manager directories
+    pub fn serves(&mut self, dirs: &[PathBuf]) -> bool {
+        match self.request(&Request::Dirs) {
+            Ok(value) => serde_json::from_value::<Vec<PathBuf>>(value)
+                .is_ok_and(|served| served == absolute_dirs(dirs)),
+            Err(e) => {
+                trace!("Could not ask the daemon for its directories: {}", e);
+                false
+            }
+        }
+    }
+
+    pub fn request(&mut self, request: &Request) -> Result<Value, Error> {
+        send(&mut self.writer, request)?;
+
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(Error::Daemon(String::from("connection closed")));
+        }
+        match serde_json::from_str(&line)? {
+            Reply::Ok(value) => Ok(value),
+            Reply::Error(e) => Err(Error::Daemon(e)),
+        }
+    }
+
+    /// Calls `on_event` with every event until the daemon goes away
+    pub fn subscribe(mut self, mut on_event: impl FnMut(Event)) -> Result<(), Error> {
+        self.request(&Request::Subscribe)?;
+        for line in self.reader.lines() {
+            on_event(serde_json::from_str(&line?)?);
+        }
+
+        Ok(())
+    }
+}
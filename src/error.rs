@@ -7,6 +7,12 @@ pub enum Error {
     #[error("{}", _0)]
     Deserialize(#[from] toml::de::Error),
     #[error("{}", _0)]
+    Serialize(#[from] toml::ser::Error),
+    #[error("{}", _0)]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("{}", _0)]
+    Json(#[from] serde_json::Error),
+    #[error("{}", _0)]
     Mustache(#[from] mustache::Error),
     #[error("Invalid path")]
     InvalidPath {},
@@ -18,6 +24,64 @@ pub enum Error {
     },
     #[error("Theme manager directory is not specified")]
     NoDir,
+    #[error("State directory is not specified and $HOME is not set")]
+    NoStateDir,
+    #[error("None of the theme manager directories contain install/install.toml")]
+    NoInstallFile,
+    #[error("There is no onchange hook named '{}'", _0)]
+    NoHook(String),
+    #[error("Profile '{}' does not exist", _0)]
+    NoProfile(String),
+    #[error("{}", _0)]
+    InvalidSetting(String),
+    #[error("Found {} errors while loading in strict mode", _0)]
+    Strict(usize),
+    #[error("The history does not go back {} installs", _0)]
+    NoHistory(usize),
+    #[error("Theme '{}' has no counterpart", _0)]
+    NoCounterpart(String),
+    #[error("No themes to cycle through")]
+    NoCycle,
+    #[error("No schedule is configured")]
+    NoSchedule,
+    #[error(
+        "No scheduled theme is active, the sun does not rise or set at the configured location"
+    )]
+    NoSunriseOrSunset,
+    #[error("Unit '{}' has no value '{}'", _0, _1)]
+    NoValue(String, String),
+    #[error("A daemon is already listening on {:?}", _0)]
+    DaemonRunning(std::path::PathBuf),
+    #[error("No daemon is running")]
+    NoDaemon,
+    #[error("Daemon: {}", _0)]
+    Daemon(String),
+    #[error("No theme is installed")]
+    NotInstalled,
+    #[error("Theme '{}' does not exist", _0)]
+    NoTheme(String),
+    #[error("{:?} is already a theme manager directory", _0)]
+    AlreadyInitialized(std::path::PathBuf),
+    #[error("Theme '{}' already exists", _0)]
+    ThemeExists(String),
+    #[error("'{}' is not a valid name", _0)]
+    InvalidName(String),
+    #[error("Invalid theme archive: {}", _0)]
+    InvalidArchive(String),
+    #[error("Unknown format '{}'", _0)]
+    UnknownFormat(String),
+    #[error("Invalid color scheme: {}", _0)]
+    InvalidScheme(String),
+    #[error("Values '{}' and '{}' both map to {}", first, second, name)]
+    EnvVarCollision {
+        name: String,
+        first: String,
+        second: String,
+    },
+    #[error("Inheritance cycle: {}", _0)]
+    InheritCycle(String),
+    #[error("Check found {} errors and {} warnings", errors, warnings)]
+    CheckFailed { errors: usize, warnings: usize },
     #[error("{}: {}", context, inner)]
     Context { context: String, inner: Box<Error> },
 }
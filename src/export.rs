@@ -0,0 +1,334 @@
+use std::{collections::BTreeMap, fmt::Write, str::FromStr};
+
+use serde::Serialize;
+
+use crate::import::{base16::BASE16, Color, Palette};
+use crate::prelude::*;
+
+/// Values of every unit after resolving the theme chain
+pub type ResolvedUnits = BTreeMap<String, BTreeMap<String, String>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Base16,
+    Xresources,
+    Json,
+    Shell,
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base16" => Ok(ExportFormat::Base16),
+            "xresources" => Ok(ExportFormat::Xresources),
+            "json" => Ok(ExportFormat::Json),
+            "shell" => Ok(ExportFormat::Shell),
+            _ => Err(Error::UnknownFormat(String::from(s))),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct JsonExport<'a> {
+    theme: &'a str,
+    chain: &'a [String],
+    units: &'a ResolvedUnits,
+}
+
+#[derive(Serialize)]
+struct Base16Export<'a> {
+    scheme: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    author: Option<&'a String>,
+    #[serde(flatten)]
+    colors: BTreeMap<String, String>,
+}
+
+/// Renders a resolved theme. `palette_unit` is the unit colors are taken from for formats
+/// that only contain colors.
+pub fn export(
+    format: ExportFormat,
+    theme: &str,
+    chain: &[String],
+    units: &ResolvedUnits,
+    palette_unit: &str,
+) -> Result<String, Error> {
+    let empty = BTreeMap::new();
+    let palette_values = units.get(palette_unit).unwrap_or(&empty);
+
+    match format {
+        ExportFormat::Json => {
+            let export = JsonExport {
+                theme,
+                chain,
+                units,
+            };
+            Ok(serde_json::to_string_pretty(&export)? + "\n")
+        }
+
+        ExportFormat::Shell => {
+            let mut out = String::new();
+            for (name, value) in env_vars(units)? {
+                writeln!(out, "export {}='{}'", name, value.replace('\'', "'\\''")).unwrap();
+            }
+            Ok(out)
+        }
+
+        ExportFormat::Xresources => {
+            let palette = Palette::from_values(palette_values);
+            let named = [
+                ("foreground", palette.foreground),
+                ("background", palette.background),
+                ("cursorColor", palette.cursor),
+                ("highlightColor", palette.selection),
+            ];
+
+            let mut out = String::new();
+            for (name, color) in named.iter() {
+                if let Some(color) = color {
+                    writeln!(out, "*.{}: {}", name, color).unwrap();
+                }
+            }
+            for (i, color) in palette.ansi.iter().enumerate() {
+                if let Some(color) = color {
+                    writeln!(out, "*.color{}: {}", i, color).unwrap();
+                }
+            }
+            Ok(out)
+        }
+
+        ExportFormat::Base16 => {
+            let colors = base16_colors(palette_values).map_err(|missing| {
+                Error::InvalidScheme(format!(
+                    "Unit '{}' is missing {}",
+                    palette_unit,
+                    missing.join(", ")
+                ))
+            })?;
+
+            let export = Base16Export {
+                scheme: palette_values
+                    .get("scheme-name")
+                    .map(|name| name.as_str())
+                    .unwrap_or(theme),
+                author: palette_values.get("scheme-author"),
+                colors,
+            };
+            Ok(serde_yaml::to_string(&export)?)
+        }
+    }
+}
+
+/// The sixteen base16 colors, taken from `base00`..`base0F` if the unit has any of them and
+/// derived from the terminal colors otherwise. Returns the names of the missing values.
+fn base16_colors(
+    values: &BTreeMap<String, String>,
+) -> Result<BTreeMap<String, String>, Vec<String>> {
+    let color = |name: &str| values.get(name).and_then(|value| Color::from_hex(value));
+    let mut missing = Vec::new();
+    let mut colors = BTreeMap::new();
+
+    if BASE16.iter().any(|name| values.contains_key(*name)) {
+        for name in BASE16 {
+            match color(name) {
+                Some(color) => {
+                    colors.insert(String::from(*name), color.hex());
+                }
+                None => missing.push(String::from(*name)),
+            }
+        }
+        return if missing.is_empty() {
+            Ok(colors)
+        } else {
+            Err(missing)
+        };
+    }
+
+    // The reverse of the mapping base16-shell uses, with the shades that have no terminal color
+    // of their own blended from their neighbours
+    let mut require = |names: &[&str]| match names.iter().find_map(|name| color(name)) {
+        Some(color) => color,
+        None => {
+            missing.push(String::from(names[0]));
+            Color { r: 0, g: 0, b: 0 }
+        }
+    };
+    let base00 = require(&["background", "color0"]);
+    let base03 = require(&["color8"]);
+    let base05 = require(&["foreground", "color7"]);
+    let base07 = require(&["color15"]);
+    let red = require(&["color1"]);
+    let green = require(&["color2"]);
+    let yellow = require(&["color3"]);
+    let blue = require(&["color4"]);
+    let magenta = require(&["color5"]);
+    let cyan = require(&["color6"]);
+    if !missing.is_empty() {
+        return Err(missing);
+    }
+
+    let derived = [
+        base00,
+        mix(base00, base03, 1.0 / 3.0),
+        color("selection").unwrap_or_else(|| mix(base00, base03, 2.0 / 3.0)),
+        base03,
+        mix(base03, base05, 0.5),
+        base05,
+        mix(base05, base07, 0.5),
+        base07,
+        red,
+        mix(red, yellow, 0.5),
+        yellow,
+        green,
+        cyan,
+        blue,
+        magenta,
+        mix(red, base00, 0.5),
+    ];
+    for (name, color) in BASE16.iter().zip(derived) {
+        colors.insert(String::from(*name), color.hex());
+    }
+
+    Ok(colors)
+}
+
+/// Blends `a` into `b`, `t` being the share of `b`
+fn mix(a: Color, b: Color, t: f64) -> Color {
+    let channel = |a: u8, b: u8| (a as f64 + (b as f64 - a as f64) * t).round() as u8;
+    Color {
+        r: channel(a.r, b.r),
+        g: channel(a.g, b.g),
+        b: channel(a.b, b.b),
+    }
+}
+
+/// Every unit value by its environment variable name.
+///
+/// Fails if two values map to the same name, like `font-size` and `font_size`.
+pub fn env_vars(units: &ResolvedUnits) -> Result<BTreeMap<String, &str>, Error> {
+    let mut vars = BTreeMap::new();
+    let mut sources = BTreeMap::<String, String>::new();
+    for (unit_name, values) in units {
+        for (value_name, value) in values {
+            let name = env_var_name(unit_name, value_name);
+            let source = format!("{}.{}", unit_name, value_name);
+            if let Some(first) = sources.insert(name.clone(), source.clone()) {
+                return Err(Error::EnvVarCollision {
+                    name,
+                    first,
+                    second: source,
+                });
+            }
+            vars.insert(name, value.as_str());
+        }
+    }
+
+    Ok(vars)
+}
+
+/// Environment variable name for a unit value, e.g. `TM_UNIT_KITTY_BG`
+pub fn env_var_name(unit_name: &str, value_name: &str) -> String {
+    let sanitize = |s: &str| {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>()
+    };
+
+    format!("TM_UNIT_{}_{}", sanitize(unit_name), sanitize(value_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn units() -> ResolvedUnits {
+        let palette = vec![
+            ("background", "#1d2021"),
+            ("foreground", "#d5c4a1"),
+            ("color1", "#fb4934"),
+        ];
+        let kitty = vec![("font-size", "11"), ("quote", "it's")];
+
+        let unit = |values: Vec<(&str, &str)>| {
+            values
+                .into_iter()
+                .map(|(k, v)| (String::from(k), String::from(v)))
+                .collect()
+        };
+
+        vec![
+            (String::from("palette"), unit(palette)),
+            (String::from("kitty"), unit(kitty)),
+        ]
+        .into_iter()
+        .collect()
+    }
+
+    #[test]
+    fn shell() {
+        let out = export(ExportFormat::Shell, "dark", &[], &units(), "palette").unwrap();
+        assert!(out.contains("export TM_UNIT_KITTY_FONT_SIZE='11'\n"));
+        assert!(out.contains("export TM_UNIT_KITTY_QUOTE='it'\\''s'\n"));
+
+        let mut units = units();
+        let kitty = units.get_mut("kitty").unwrap();
+        kitty.insert(String::from("font_size"), String::from("12"));
+        let err = export(ExportFormat::Shell, "dark", &[], &units, "palette").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Values 'kitty.font-size' and 'kitty.font_size' both map to TM_UNIT_KITTY_FONT_SIZE"
+        );
+    }
+
+    #[test]
+    fn xresources() {
+        let out = export(ExportFormat::Xresources, "dark", &[], &units(), "palette").unwrap();
+        assert_eq!(
+            out,
+            "*.foreground: #d5c4a1\n*.background: #1d2021\n*.color1: #fb4934\n"
+        );
+    }
+
+    #[test]
+    fn base16_requires_colors() {
+        assert!(export(ExportFormat::Base16, "dark", &[], &units(), "palette").is_err());
+
+        // base10 and above don't make up for a missing base0F
+        let mut units = units();
+        let palette = units.get_mut("palette").unwrap();
+        for name in BASE16.iter().take(15).chain(&["base10", "base11"]) {
+            palette.insert(String::from(*name), String::from("#000000"));
+        }
+        let err = export(ExportFormat::Base16, "dark", &[], &units, "palette").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid color scheme: Unit 'palette' is missing base0F"
+        );
+    }
+
+    #[test]
+    fn base16_from_terminal_colors() {
+        let mut units = units();
+        let palette = units.get_mut("palette").unwrap();
+        for i in 0..16 {
+            palette
+                .entry(format!("color{}", i))
+                .or_insert_with(|| format!("#{:02x}{:02x}{:02x}", i * 16, i * 16, i * 16));
+        }
+
+        let out = export(ExportFormat::Base16, "dark", &[], &units, "palette").unwrap();
+        assert!(out.contains("base00: 1d2021\n"));
+        assert!(out.contains("base05: d5c4a1\n"));
+        assert!(out.contains("base08: fb4934\n"));
+        assert!(out.contains("base03: \"808080\"\n"));
+        assert!(out.contains("base09: 963d32\n"));
+    }
+}
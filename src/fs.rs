@@ -0,0 +1,548 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    io::{Error as IoError, ErrorKind, Result as IoResult, Write},
+    os::unix::fs::PermissionsExt,
+    path::{Component, Path, PathBuf},
+    sync::Mutex,
+};
+
+/// An entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+}
+
+/// The file operations used to load manager directories and install files
+pub trait Fs: Debug + Send + Sync {
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>>;
+
+    fn write(&self, path: &Path, contents: &[u8]) -> IoResult<()>;
+
+    /// Appends to the file, creating it if it does not exist
+    fn append(&self, path: &Path, contents: &[u8]) -> IoResult<()>;
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> IoResult<()>;
+
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()>;
+
+    /// Entries of the directory in no particular order
+    fn read_dir(&self, dir: &Path) -> IoResult<Vec<DirEntry>>;
+
+    fn is_file(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn is_executable(&self, path: &Path) -> bool;
+
+    fn set_executable(&self, path: &Path) -> IoResult<()>;
+
+    fn exists(&self, path: &Path) -> bool {
+        self.is_file(path) || self.is_dir(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> IoResult<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| IoError::new(ErrorKind::InvalidData, e))
+    }
+
+    /// Copies the contents and whether the file is executable
+    fn copy(&self, from: &Path, to: &Path) -> IoResult<()> {
+        self.write(to, &self.read(from)?)?;
+        if self.is_executable(from) {
+            self.set_executable(to)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The actual filesystem
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+impl Fs for RealFs {
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>> {
+        std::fs::read(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> IoResult<()> {
+        std::fs::write(path, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> IoResult<()> {
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(contents)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()> {
+        std::fs::create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> IoResult<()> {
+        std::fs::remove_dir_all(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()> {
+        std::fs::rename(from, to)
+    }
+
+    fn read_dir(&self, dir: &Path) -> IoResult<Vec<DirEntry>> {
+        let entries = std::fs::read_dir(dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                // Follows symlinks, unlike `DirEntry::file_type`
+                let metadata = std::fs::metadata(&path).ok()?;
+                Some(DirEntry {
+                    path,
+                    is_dir: metadata.is_dir(),
+                })
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        path.is_file()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        std::fs::metadata(path)
+            .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+    }
+
+    fn set_executable(&self, path: &Path) -> IoResult<()> {
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        permissions.set_mode(permissions.mode() | 0o111);
+        std::fs::set_permissions(path, permissions)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> IoResult<()> {
+        std::fs::copy(from, to).map(|_| ())
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    File { contents: Vec<u8>, executable: bool },
+    Dir,
+}
+
+/// A filesystem that only exists in memory, rooted at `/`
+#[derive(Debug)]
+pub struct MemoryFs {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+}
+
+impl Default for MemoryFs {
+    fn default() -> Self {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(PathBuf::from("/"), Node::Dir);
+
+        MemoryFs {
+            nodes: Mutex::new(nodes),
+        }
+    }
+}
+
+impl MemoryFs {
+    /// Creates the files along with their parent directories
+    pub fn with_files<P, C>(files: impl IntoIterator<Item = (P, C)>) -> Self
+    where
+        P: AsRef<Path>,
+        C: AsRef<[u8]>,
+    {
+        let fs = MemoryFs::default();
+        for (path, contents) in files {
+            let path = path.as_ref();
+            if let Some(parent) = path.parent() {
+                fs.create_dir_all(parent).unwrap();
+            }
+            fs.write(path, contents.as_ref()).unwrap();
+        }
+
+        fs
+    }
+
+    /// Paths and contents of every file
+    pub fn files(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, node)| match node {
+                Node::File { contents, .. } => Some((path.clone(), contents.clone())),
+                Node::Dir => None,
+            })
+            .collect()
+    }
+}
+
+/// Resolves `.` and `..` so that equal paths have equal keys
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+
+    normalized
+}
+
+fn not_found(path: &Path) -> IoError {
+    IoError::new(ErrorKind::NotFound, format!("{:?} does not exist", path))
+}
+
+impl MemoryFs {
+    fn check_parent(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> IoResult<()> {
+        match path.parent().map(|parent| nodes.get(parent)) {
+            Some(Some(Node::Dir)) | None => Ok(()),
+            _ => Err(not_found(path.parent().unwrap())),
+        }
+    }
+}
+
+impl Fs for MemoryFs {
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(&normalize(path)) {
+            Some(Node::File { contents, .. }) => Ok(contents.clone()),
+            Some(Node::Dir) => Err(IoError::other(format!("{:?} is a directory", path))),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> IoResult<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+        Self::check_parent(&nodes, &path)?;
+        match nodes.get_mut(&path) {
+            Some(Node::Dir) => Err(IoError::other(format!("{:?} is a directory", path))),
+            // Like a real file, an overwritten one stays executable
+            Some(Node::File {
+                contents: existing, ..
+            }) => {
+                *existing = contents.to_owned();
+                Ok(())
+            }
+            None => {
+                nodes.insert(
+                    path,
+                    Node::File {
+                        contents: contents.to_owned(),
+                        executable: false,
+                    },
+                );
+                Ok(())
+            }
+        }
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> IoResult<()> {
+        let mut existing = match self.read(path) {
+            Ok(existing) => existing,
+            Err(e) if e.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        existing.extend_from_slice(contents);
+        self.write(path, &existing)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+        for dir in path.ancestors() {
+            match nodes.get(dir) {
+                Some(Node::Dir) => {}
+                Some(Node::File { .. }) => {
+                    return Err(IoError::other(format!("{:?} is a file", dir)));
+                }
+                None => {
+                    nodes.insert(dir.to_owned(), Node::Dir);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> IoResult<()> {
+        let path = normalize(path);
+        let mut nodes = self.nodes.lock().unwrap();
+        match nodes.get(&path) {
+            Some(Node::Dir) => {}
+            Some(Node::File { .. }) => {
+                return Err(IoError::other(format!("{:?} is not a directory", path)))
+            }
+            None => return Err(not_found(&path)),
+        }
+
+        nodes.retain(|node_path, _| !node_path.starts_with(&path));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> IoResult<()> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut nodes = self.nodes.lock().unwrap();
+        if !nodes.contains_key(&from) {
+            return Err(not_found(&from));
+        }
+        Self::check_parent(&nodes, &to)?;
+        if nodes.contains_key(&to) {
+            return Err(IoError::new(
+                ErrorKind::AlreadyExists,
+                format!("{:?} already exists", to),
+            ));
+        }
+
+        let moved = nodes
+            .keys()
+            .filter(|path| path.starts_with(&from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for path in moved {
+            let node = nodes.remove(&path).unwrap();
+            nodes.insert(to.join(path.strip_prefix(&from).unwrap()), node);
+        }
+
+        Ok(())
+    }
+
+    fn read_dir(&self, dir: &Path) -> IoResult<Vec<DirEntry>> {
+        let dir = normalize(dir);
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(&dir) {
+            Some(Node::Dir) => {}
+            Some(Node::File { .. }) => {
+                return Err(IoError::other(format!("{:?} is not a directory", dir)))
+            }
+            None => return Err(not_found(&dir)),
+        }
+
+        let entries = nodes
+            .iter()
+            .filter(|(path, _)| path.parent() == Some(dir.as_path()))
+            .map(|(path, node)| DirEntry {
+                path: path.clone(),
+                is_dir: matches!(node, Node::Dir),
+            })
+            .collect();
+
+        Ok(entries)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        matches!(
+            self.nodes.lock().unwrap().get(&normalize(path)),
+            Some(Node::File { .. })
+        )
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        matches!(
+            self.nodes.lock().unwrap().get(&normalize(path)),
+            Some(Node::Dir)
+        )
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        matches!(
+            self.nodes.lock().unwrap().get(&normalize(path)),
+            Some(Node::File {
+                executable: true,
+                ..
+            })
+        )
+    }
+
+    fn set_executable(&self, path: &Path) -> IoResult<()> {
+        match self.nodes.lock().unwrap().get_mut(&normalize(path)) {
+            Some(Node::File { executable, .. }) => {
+                *executable = true;
+                Ok(())
+            }
+            Some(Node::Dir) => Ok(()),
+            None => Err(not_found(path)),
+        }
+    }
+}
+
+/// Reads from another filesystem but keeps everything written in memory, recording what would
+/// have been written. Removing and renaming are not supported.
+#[derive(Debug)]
+pub struct DryRunFs<'a> {
+    inner: &'a dyn Fs,
+    written: MemoryFs,
+}
+
+impl<'a> DryRunFs<'a> {
+    pub fn new(inner: &'a dyn Fs) -> Self {
+        DryRunFs {
+            inner,
+            written: MemoryFs::default(),
+        }
+    }
+
+    /// Files that would have been written, with their contents
+    pub fn written(&self) -> Vec<(PathBuf, Vec<u8>)> {
+        self.written.files()
+    }
+}
+
+impl Fs for DryRunFs<'_> {
+    fn read(&self, path: &Path) -> IoResult<Vec<u8>> {
+        if self.written.is_file(path) {
+            self.written.read(path)
+        } else {
+            self.inner.read(path)
+        }
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> IoResult<()> {
+        if let Some(parent) = path.parent() {
+            if !self.is_dir(parent) {
+                return Err(not_found(parent));
+            }
+            self.written.create_dir_all(parent)?;
+        }
+        self.written.write(path, contents)
+    }
+
+    fn append(&self, path: &Path, contents: &[u8]) -> IoResult<()> {
+        let mut existing = if self.is_file(path) {
+            self.read(path)?
+        } else {
+            Vec::new()
+        };
+        existing.extend_from_slice(contents);
+        self.write(path, &existing)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> IoResult<()> {
+        if path.ancestors().any(|dir| self.inner.is_file(dir)) {
+            return Err(IoError::other(format!("{:?} is a file", path)));
+        }
+        self.written.create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> IoResult<()> {
+        Err(unsupported("remove", path))
+    }
+
+    fn rename(&self, from: &Path, _to: &Path) -> IoResult<()> {
+        Err(unsupported("rename", from))
+    }
+
+    fn read_dir(&self, dir: &Path) -> IoResult<Vec<DirEntry>> {
+        let mut entries = self.inner.read_dir(dir).or_else(|e| {
+            if self.written.is_dir(dir) {
+                Ok(Vec::new())
+            } else {
+                Err(e)
+            }
+        })?;
+        for entry in self.written.read_dir(dir).unwrap_or_default() {
+            if !entries.iter().any(|e| e.path == entry.path) {
+                entries.push(entry);
+            }
+        }
+
+        Ok(entries)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.written.is_file(path) || self.inner.is_file(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.written.is_dir(path) || self.inner.is_dir(path)
+    }
+
+    fn is_executable(&self, path: &Path) -> bool {
+        if self.written.is_file(path) {
+            self.written.is_executable(path)
+        } else {
+            self.inner.is_executable(path)
+        }
+    }
+
+    fn set_executable(&self, path: &Path) -> IoResult<()> {
+        if !self.written.is_file(path) {
+            self.write(path, &self.inner.read(path)?)?;
+        }
+        self.written.set_executable(path)
+    }
+}
+
+fn unsupported(operation: &str, path: &Path) -> IoError {
+    IoError::new(
+        ErrorKind::Unsupported,
+        format!("Cannot {} {:?} in a dry run", operation, path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_fs() {
+        let fs = MemoryFs::with_files([("/a/b/c.txt", "c")]);
+
+        assert_eq!(
+            fs.read_to_string(Path::new("/a/b/../b/c.txt")).unwrap(),
+            "c"
+        );
+        assert!(fs.is_dir(Path::new("/a/b")));
+        assert!(fs.write(Path::new("/x/y.txt"), b"y").is_err());
+        assert_eq!(
+            fs.read_dir(Path::new("/a")).unwrap(),
+            vec![DirEntry {
+                path: PathBuf::from("/a/b"),
+                is_dir: true,
+            }]
+        );
+
+        fs.set_executable(Path::new("/a/b/c.txt")).unwrap();
+        fs.rename(Path::new("/a/b"), Path::new("/d")).unwrap();
+        assert!(fs.is_executable(Path::new("/d/c.txt")));
+        fs.remove_dir_all(Path::new("/d")).unwrap();
+        assert_eq!(fs.files(), Vec::new());
+    }
+
+    #[test]
+    fn dry_run_keeps_writes() {
+        let inner = MemoryFs::with_files([("/a/b.txt", "old")]);
+        let fs = DryRunFs::new(&inner);
+
+        fs.write(Path::new("/a/b.txt"), b"new").unwrap();
+        fs.create_dir_all(Path::new("/c")).unwrap();
+        fs.write(Path::new("/c/d.txt"), b"d").unwrap();
+
+        assert_eq!(fs.read_to_string(Path::new("/a/b.txt")).unwrap(), "new");
+        assert_eq!(inner.read_to_string(Path::new("/a/b.txt")).unwrap(), "old");
+        assert!(!inner.exists(Path::new("/c")));
+        assert_eq!(fs.read_dir(Path::new("/")).unwrap().len(), 2);
+        assert_eq!(
+            fs.written()
+                .into_iter()
+                .map(|(path, _)| path)
+                .collect::<Vec<_>>(),
+            vec![PathBuf::from("/a/b.txt"), PathBuf::from("/c/d.txt")]
+        );
+    }
+}
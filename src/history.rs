@@ -0,0 +1,330 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+
+use crate::fs::Fs;
+use crate::prelude::*;
+
+/// Number of installs that can be rolled back to, snapshots only older ones refer to are removed
+const KEEP_SNAPSHOTS: usize = 20;
+
+/// One install as recorded in the history log
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Entry {
+    /// Seconds since the Unix epoch
+    pub timestamp: i64,
+    pub theme: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// Snapshot of the installed files under `history/`, missing if the install failed or the
+    /// snapshot was pruned
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manifest: Option<String>,
+    pub success: bool,
+}
+
+impl Entry {
+    pub fn time(&self) -> String {
+        Local
+            .timestamp_opt(self.timestamp, 0)
+            .single()
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| self.timestamp.to_string())
+    }
+}
+
+/// Contents of a snapshot
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Manifest {
+    #[serde(default)]
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct ManifestFile {
+    target: PathBuf,
+    /// File inside the snapshot directory holding the installed contents
+    stored: String,
+}
+
+/// The history log, oldest entry first
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct History {
+    #[serde(default, rename = "install")]
+    pub entries: Vec<Entry>,
+}
+
+impl History {
+    /// Successful installs that can be returned to, most recent first
+    pub fn restorable(&self) -> impl Iterator<Item = &Entry> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|entry| entry.success && entry.manifest.is_some())
+    }
+
+    /// The install `steps` installs before the current one
+    pub fn previous(&self, steps: usize) -> Option<&Entry> {
+        self.restorable().nth(steps)
+    }
+}
+
+fn log_file(state_dir: &Path) -> PathBuf {
+    state_dir.join("history.toml")
+}
+
+fn snapshot_dir(state_dir: &Path, manifest: &str) -> PathBuf {
+    state_dir.join("history").join(manifest)
+}
+
+pub fn read(fs: &dyn Fs, state_dir: &Path) -> Result<History, Error> {
+    let path = log_file(state_dir);
+    if !fs.exists(&path) {
+        return Ok(History::default());
+    }
+
+    let s = fs.read_to_string(&path).context("Could not read history")?;
+    let mut history: History = toml::de::from_str(&s).context("history.toml parse error")?;
+    for entry in &mut history.entries {
+        if let Some(ref manifest) = entry.manifest {
+            if !fs.exists(&snapshot_dir(state_dir, manifest)) {
+                entry.manifest = None;
+            }
+        }
+    }
+
+    Ok(history)
+}
+
+/// Appends an entry to the history log
+pub fn append(fs: &dyn Fs, state_dir: &Path, entry: Entry) -> Result<(), Error> {
+    let s = toml::ser::to_string(&History {
+        entries: vec![entry],
+    })?;
+
+    fs.create_dir_all(state_dir)
+        .context("Could not create state directory")?;
+    fs.append(&log_file(state_dir), format!("{}\n", s).as_bytes())
+        .context("Could not write history")?;
+
+    Ok(())
+}
+
+/// Records an install, keeping a copy of every installed file if it succeeded
+pub fn record(
+    fs: &dyn Fs,
+    state_dir: &Path,
+    theme: &str,
+    profile: Option<&str>,
+    installed: Option<&[PathBuf]>,
+) -> Result<(), Error> {
+    let timestamp = Local::now().timestamp();
+    let manifest = match installed {
+        Some(installed) => {
+            // Numbered by position in the log, so that the name of a pruned snapshot that older
+            // entries still mention is never reused
+            let position = read(fs, state_dir).map_or(0, |history| history.entries.len());
+            let mut name = format!("{}-{}", timestamp, position);
+            for i in 1.. {
+                if !fs.exists(&snapshot_dir(state_dir, &name)) {
+                    break;
+                }
+                name = format!("{}-{}-{}", timestamp, position, i);
+            }
+            snapshot(fs, &snapshot_dir(state_dir, &name), installed)?;
+            Some(name)
+        }
+        None => None,
+    };
+
+    append(
+        fs,
+        state_dir,
+        Entry {
+            timestamp,
+            theme: String::from(theme),
+            profile: profile.map(String::from),
+            manifest,
+            success: installed.is_some(),
+        },
+    )?;
+
+    if installed.is_some() {
+        if let Err(e) = prune(fs, state_dir) {
+            warn!("{}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn snapshot(fs: &dyn Fs, dir: &Path, installed: &[PathBuf]) -> Result<(), Error> {
+    trace!("Recording {} installed files in {:?}", installed.len(), dir);
+
+    let files_dir = dir.join("files");
+    fs.create_dir_all(&files_dir)
+        .context("Could not create snapshot directory")?;
+
+    let mut manifest = Manifest::default();
+    for (i, target) in installed.iter().enumerate() {
+        let stored = i.to_string();
+        fs.copy(target, &files_dir.join(&stored))
+            .with_context(|| format!("Could not record {:?}", target))?;
+        manifest.files.push(ManifestFile {
+            target: target.clone(),
+            stored,
+        });
+    }
+
+    fs.write(
+        &dir.join("manifest.toml"),
+        toml::ser::to_string(&manifest)?.as_bytes(),
+    )
+    .context("Could not write manifest")?;
+
+    Ok(())
+}
+
+/// Removes the snapshots none of the most recent installs refer to. A rollback refers to the
+/// snapshot of the install it returned to, so that one is kept however old it is.
+fn prune(fs: &dyn Fs, state_dir: &Path) -> Result<(), Error> {
+    let history = read(fs, state_dir)?;
+    let keep = history
+        .restorable()
+        .take(KEEP_SNAPSHOTS)
+        .filter_map(|entry| entry.manifest.as_deref())
+        .collect::<HashSet<_>>();
+
+    let dir = state_dir.join("history");
+    for entry in fs.read_dir(&dir).context("Could not read snapshots")? {
+        let name = entry.path.file_name().and_then(|name| name.to_str());
+        if name.is_some_and(|name| !keep.contains(name)) {
+            trace!("Removing snapshot {:?}", entry.path);
+            fs.remove_dir_all(&entry.path)
+                .with_context(|| format!("Could not remove snapshot {:?}", entry.path))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes back the files exactly as they were installed, returning their paths
+pub fn restore(fs: &dyn Fs, state_dir: &Path, entry: &Entry) -> Result<Vec<PathBuf>, Error> {
+    let manifest = entry
+        .manifest
+        .as_ref()
+        .expect("Restoring a failed install. This is probably a bug");
+    let dir = snapshot_dir(state_dir, manifest);
+
+    let s = fs
+        .read_to_string(&dir.join("manifest.toml"))
+        .context("Could not read manifest")?;
+    let manifest: Manifest = toml::de::from_str(&s).context("manifest.toml parse error")?;
+
+    let mut restored = Vec::new();
+    for file in manifest.files {
+        trace!("Restoring {:?}", file.target);
+        if let Some(parent) = file.target.parent() {
+            fs.create_dir_all(parent)
+                .context("Failed to create parent directory")?;
+        }
+        fs.copy(&dir.join("files").join(&file.stored), &file.target)
+            .with_context(|| format!("Could not restore {:?}", file.target))?;
+        restored.push(file.target);
+    }
+
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+
+    fn entry(theme: &str, success: bool) -> Entry {
+        Entry {
+            timestamp: 0,
+            theme: String::from(theme),
+            profile: None,
+            manifest: if success {
+                Some(String::from(theme))
+            } else {
+                None
+            },
+            success,
+        }
+    }
+
+    #[test]
+    fn previous_skips_failures() {
+        let history = History {
+            entries: vec![entry("a", true), entry("b", true), entry("c", false)],
+        };
+
+        assert_eq!(history.previous(0).unwrap().theme, "b");
+        assert_eq!(history.previous(1).unwrap().theme, "a");
+        assert!(history.previous(2).is_none());
+    }
+
+    #[test]
+    fn appended_entries_parse() {
+        let mut s = String::new();
+        for entry in [entry("a", true), entry("b", false)] {
+            s += &toml::ser::to_string(&History {
+                entries: vec![entry],
+            })
+            .unwrap();
+            s += "\n";
+        }
+
+        let history: History = toml::de::from_str(&s).unwrap();
+        assert_eq!(history.entries.len(), 2);
+        assert_eq!(history.entries[1].theme, "b");
+        assert!(!history.entries[1].success);
+    }
+
+    #[test]
+    fn old_snapshots_are_pruned() {
+        let fs = MemoryFs::with_files([("/target.conf", "a")]);
+        let state_dir = Path::new("/state");
+
+        let installed = vec![PathBuf::from("/target.conf")];
+        for _ in 0..KEEP_SNAPSHOTS + 2 {
+            record(&fs, state_dir, "a", None, Some(&installed)).unwrap();
+        }
+        let history = read(&fs, state_dir).unwrap();
+        let snapshots = fs.read_dir(&state_dir.join("history")).unwrap().len();
+
+        assert_eq!(snapshots, KEEP_SNAPSHOTS);
+        assert_eq!(history.entries.len(), KEEP_SNAPSHOTS + 2);
+        assert_eq!(history.restorable().count(), KEEP_SNAPSHOTS);
+        assert!(history.entries[0].manifest.is_none());
+    }
+
+    #[test]
+    fn rolled_back_snapshot_is_kept() {
+        let fs = MemoryFs::with_files([("/target.conf", "a")]);
+        let state_dir = Path::new("/state");
+        let installed = vec![PathBuf::from("/target.conf")];
+
+        record(&fs, state_dir, "a", None, Some(&installed)).unwrap();
+        fs.write(Path::new("/target.conf"), b"b").unwrap();
+        for _ in 0..KEEP_SNAPSHOTS - 1 {
+            record(&fs, state_dir, "b", None, Some(&installed)).unwrap();
+        }
+        // What a rollback to the first install appends
+        let first = read(&fs, state_dir).unwrap().entries[0].clone();
+        append(&fs, state_dir, first).unwrap();
+        record(&fs, state_dir, "b", None, Some(&installed)).unwrap();
+
+        let history = read(&fs, state_dir).unwrap();
+        let entry = history.previous(1).unwrap();
+        assert_eq!(entry.theme, "a");
+        restore(&fs, state_dir, entry).unwrap();
+        assert_eq!(fs.read_to_string(Path::new("/target.conf")).unwrap(), "a");
+    }
+}
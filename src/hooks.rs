@@ -1,8 +1,17 @@
 use std::{
+    collections::HashMap,
+    convert::TryFrom,
     path::{Path, PathBuf},
-    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
 };
 
+use serde::{Deserialize, Serialize};
+
+use crate::check::Report;
+use crate::command::{CommandLine, CommandRunner, RunOptions};
+use crate::config::{FailurePolicy, HookConfig};
+use crate::fs::Fs;
 use crate::prelude::*;
 use utils::tree_reader::{TreeReader, TreeReaderNode};
 
@@ -12,6 +21,8 @@ pub struct HookSet {
     pub postinstall: Hook,
     pub preremove: Hook,
     pub postremove: Hook,
+    /// Only run when a file referencing them by name changed
+    pub onchange: Hook,
 }
 
 impl Default for HookSet {
@@ -37,6 +48,11 @@ impl Default for HookSet {
                 name: String::from("Postremove"),
                 executables: Vec::new(),
             },
+            onchange: Hook {
+                global: false,
+                name: String::from("Onchange"),
+                executables: Vec::new(),
+            },
         }
     }
 }
@@ -49,12 +65,278 @@ impl HookSet {
             &mut set.postinstall,
             &mut set.preremove,
             &mut set.postremove,
+            &mut set.onchange,
         ] {
             hook.global = true;
         }
 
         set
     }
+
+    /// Adds hooks declared in a `theme.toml` or `install.toml`, replacing hooks with the same name
+    pub fn add_inline(&mut self, inline: &InlineHooks) {
+        for (hook, inline) in [
+            (&mut self.preinstall, &inline.preinstall),
+            (&mut self.postinstall, &inline.postinstall),
+            (&mut self.preremove, &inline.preremove),
+            (&mut self.postremove, &inline.postremove),
+            (&mut self.onchange, &inline.onchange),
+        ] {
+            for inline_hook in inline {
+                hook.add_action(
+                    inline_hook.name.clone(),
+                    HookAction::Inline(inline_hook.clone()),
+                );
+            }
+        }
+    }
+
+    /// Adds hooks from another set, replacing hooks with the same name
+    pub fn extend(&mut self, other: HookSet) {
+        self.preinstall.extend(other.preinstall);
+        self.postinstall.extend(other.postinstall);
+        self.preremove.extend(other.preremove);
+        self.postremove.extend(other.postremove);
+        self.onchange.extend(other.onchange);
+    }
+}
+
+/// Hooks declared as `[[hooks.<phase>]]` tables
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InlineHooks {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preinstall: Vec<InlineHook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub postinstall: Vec<InlineHook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preremove: Vec<InlineHook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub postremove: Vec<InlineHook>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub onchange: Vec<InlineHook>,
+}
+
+impl InlineHooks {
+    pub fn is_empty(&self) -> bool {
+        self.preinstall.is_empty()
+            && self.postinstall.is_empty()
+            && self.preremove.is_empty()
+            && self.postremove.is_empty()
+            && self.onchange.is_empty()
+    }
+
+    fn all_mut(&mut self) -> impl Iterator<Item = &mut InlineHook> {
+        self.preinstall
+            .iter_mut()
+            .chain(&mut self.postinstall)
+            .chain(&mut self.preremove)
+            .chain(&mut self.postremove)
+            .chain(&mut self.onchange)
+    }
+
+    /// Sets the directory the hooks were declared in, which relative working directories are
+    /// resolved against
+    pub fn set_dir(&mut self, dir: &Path) {
+        for hook in self.all_mut() {
+            hook.dir = dir.to_owned();
+        }
+    }
+
+    /// Adds hooks from another layer after the ones of this one
+    pub fn extend(&mut self, other: InlineHooks) {
+        self.preinstall.extend(other.preinstall);
+        self.postinstall.extend(other.postinstall);
+        self.preremove.extend(other.preremove);
+        self.postremove.extend(other.postremove);
+        self.onchange.extend(other.onchange);
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct InlineHook {
+    pub name: String,
+    pub command: HookCommand,
+    /// Relative to the directory of the file declaring the hook, which is also the default
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cwd: Option<PathBuf>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(untagged)]
+enum HookCommandDeserialize {
+    Argv(Vec<String>),
+    Shell(String),
+}
+
+/// How to run an inline hook
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "HookCommandDeserialize", into = "HookCommandDeserialize")]
+pub enum HookCommand {
+    /// A program and its arguments, run directly
+    Argv(Vec<String>),
+    /// Run with `sh -c`
+    Shell(String),
+}
+
+impl TryFrom<HookCommandDeserialize> for HookCommand {
+    type Error = Error;
+
+    fn try_from(value: HookCommandDeserialize) -> Result<Self, Self::Error> {
+        match value {
+            HookCommandDeserialize::Argv(argv) if argv.is_empty() => Err(Error::InvalidSetting(
+                String::from("Hook command must not be empty"),
+            )),
+            HookCommandDeserialize::Argv(argv) => Ok(HookCommand::Argv(argv)),
+            HookCommandDeserialize::Shell(command) => Ok(HookCommand::Shell(command)),
+        }
+    }
+}
+
+impl From<HookCommand> for HookCommandDeserialize {
+    fn from(command: HookCommand) -> Self {
+        match command {
+            HookCommand::Argv(argv) => HookCommandDeserialize::Argv(argv),
+            HookCommand::Shell(command) => HookCommandDeserialize::Shell(command),
+        }
+    }
+}
+
+/// What a hook runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookAction {
+    /// An executable file, run in its own directory with the theme as arguments
+    Executable(PathBuf),
+    Inline(InlineHook),
+}
+
+impl HookAction {
+    fn command(&self, theme_dir: &Path, theme_name: &str, env: &HookEnv) -> CommandLine {
+        match self {
+            HookAction::Executable(executable) => {
+                executable_command(executable, theme_dir, theme_name, env)
+            }
+            HookAction::Inline(hook) => {
+                let command = match &hook.command {
+                    HookCommand::Argv(argv) => CommandLine::new(&argv[0]).args(&argv[1..]),
+                    HookCommand::Shell(shell) => CommandLine::new("sh").arg("-c").arg(shell),
+                };
+                let cwd = match hook.cwd {
+                    Some(ref cwd) => hook.dir.join(cwd),
+                    None => hook.dir.clone(),
+                };
+                command
+                    .current_dir(cwd)
+                    .envs(env.vars())
+                    .envs(hook.env.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            }
+        }
+    }
+}
+
+/// What the hooks of one phase of an install share
+pub struct HookContext<'a> {
+    pub env: HookEnv,
+    pub summary: &'a HookSummary,
+    pub runner: &'a dyn CommandRunner,
+}
+
+/// Environment variables describing the install, passed to every hook
+#[derive(Debug, Default, Clone)]
+pub struct HookEnv {
+    vars: Vec<(String, String)>,
+}
+
+impl HookEnv {
+    pub fn set(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let value = value.into();
+        match self.vars.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) => existing.1 = value,
+            None => self.vars.push((name, value)),
+        }
+    }
+
+    /// The variables for running hooks of a phase, after the given targets changed
+    pub fn for_phase(&self, phase: &str, changed: &[PathBuf]) -> HookEnv {
+        let changed = changed
+            .iter()
+            .map(|path| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let mut env = self.clone();
+        env.set("TM_PHASE", phase);
+        env.set("TM_CHANGED_FILES", changed);
+        env
+    }
+
+    pub fn vars(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.vars
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// How one hook executable or on change command went
+#[derive(Debug, Clone)]
+pub struct HookRun {
+    pub hook: String,
+    pub executable: String,
+    pub elapsed: Duration,
+    pub error: Option<String>,
+    pub on_failure: FailurePolicy,
+}
+
+/// Results of every hook run during an install
+#[derive(Debug, Default)]
+pub struct HookSummary {
+    runs: Mutex<Vec<HookRun>>,
+}
+
+impl HookSummary {
+    fn push(&self, run: HookRun) {
+        self.runs.lock().unwrap().push(run);
+    }
+
+    pub fn runs(&self) -> Vec<HookRun> {
+        self.runs.lock().unwrap().clone()
+    }
+
+    pub fn num_failed(&self) -> usize {
+        self.runs
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|run| run.error.is_some())
+            .count()
+    }
+
+    /// Logs a line for every run, failures as warnings
+    pub fn log(&self) {
+        let runs = self.runs();
+        if runs.is_empty() {
+            return;
+        }
+
+        info!("Ran {} hooks, {} failed", runs.len(), self.num_failed());
+        for run in runs {
+            let secs = run.elapsed.as_secs_f64();
+            match run.error {
+                None => info!("  {} {}: ok ({:.2}s)", run.hook, run.executable, secs),
+                Some(ref e) if run.on_failure == FailurePolicy::Ignore => info!(
+                    "  {} {}: {}, ignored ({:.2}s)",
+                    run.hook, run.executable, e, secs
+                ),
+                Some(ref e) => warn!("  {} {}: {} ({:.2}s)", run.hook, run.executable, e, secs),
+            }
+        }
+    }
 }
 
 pub enum HookLauncher<'a> {
@@ -62,31 +344,44 @@ pub enum HookLauncher<'a> {
         theme_dir: &'a Path,
         theme_name: &'a str,
         hooks: &'a HookSet,
+        config: &'a HookConfig,
     },
 
     Empty,
 }
 
 impl<'a> HookLauncher<'a> {
-    pub fn run_preinstall(&self) -> Result<(), Error> {
+    /// Finds an `onchange` hook by name
+    pub fn find_on_change(&self, name: &str) -> Option<&'a HookAction> {
+        match self {
+            HookLauncher::HookSet { hooks, .. } => hooks.onchange.find(name),
+            HookLauncher::Empty => None,
+        }
+    }
+
+    pub fn run_preinstall(&self, context: &HookContext) -> Result<(), Error> {
         match self {
             HookLauncher::HookSet {
                 theme_dir,
                 theme_name,
                 hooks,
-            } => hooks.preinstall.run(theme_dir, theme_name),
+                config,
+            } => hooks.preinstall.run(theme_dir, theme_name, config, context),
 
             HookLauncher::Empty => Ok(()),
         }
     }
 
-    pub fn run_postinstall(&self) -> Result<(), Error> {
+    pub fn run_postinstall(&self, context: &HookContext) -> Result<(), Error> {
         match self {
             HookLauncher::HookSet {
                 theme_dir,
                 theme_name,
                 hooks,
-            } => hooks.postinstall.run(theme_dir, theme_name),
+                config,
+            } => hooks
+                .postinstall
+                .run(theme_dir, theme_name, config, context),
 
             HookLauncher::Empty => Ok(()),
         }
@@ -97,54 +392,278 @@ impl<'a> HookLauncher<'a> {
 pub struct Hook {
     global: bool,
     name: String,
-    executables: Vec<(String, PathBuf)>,
+    executables: Vec<(String, HookAction)>,
 }
 
 impl Hook {
-    fn run(&self, theme_dir: &Path, theme_name: &str) -> Result<(), Error> {
+    fn run(
+        &self,
+        theme_dir: &Path,
+        theme_name: &str,
+        config: &HookConfig,
+        context: &HookContext,
+    ) -> Result<(), Error> {
         if self.global {
             trace!("Running global {} hook for theme {}", self.name, theme_name);
         } else {
             trace!("Running {} hook for theme {}", self.name, theme_name);
         }
 
-        for (name, executable) in &self.executables {
-            trace!("Running executable '{}' at {:?}", name, executable);
-
-            let mut handle = Command::new(executable)
-                .current_dir(
-                    executable
-                        .parent()
-                        .expect("Hook path does not have a parent. This is probably a bug"),
-                )
-                .arg(theme_dir)
-                .arg(theme_name)
-                .spawn()
-                .with_context(|| format!("Failed to start {} hook '{}'", self.name, name))?;
-
-            let exit_status = handle.wait()?;
-            if !exit_status.success() {
-                return Err(Error::Hook {
-                    name: self.name.clone(),
-                    executable: name.clone(),
-                    cause: if let Some(code) = exit_status.code() {
-                        format!("finished with exit code {}", code)
-                    } else {
-                        String::from("terminated by signal")
-                    },
+        let names = self
+            .executables
+            .iter()
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        let run_one = |i: usize| {
+            let (name, action) = &self.executables[i];
+            trace!("Running '{}': {:?}", name, action);
+
+            run_recorded(
+                &self.name,
+                name,
+                &action.command(theme_dir, theme_name, &context.env),
+                config.for_hook(name),
+                context,
+            )
+        };
+
+        for batch in schedule(&names, config) {
+            if config.parallel && batch.len() > 1 {
+                let results = std::thread::scope(|scope| {
+                    let handles = batch
+                        .iter()
+                        .map(|&i| scope.spawn(move || run_one(i)))
+                        .collect::<Vec<_>>();
+                    handles
+                        .into_iter()
+                        .map(|handle| handle.join().expect("Hook thread panicked"))
+                        .collect::<Vec<_>>()
                 });
+                results.into_iter().collect::<Result<(), Error>>()?;
+            } else {
+                for i in batch {
+                    run_one(i)?;
+                }
             }
         }
 
         Ok(())
     }
 
+    /// What the hook with the given name runs
+    pub fn find(&self, name: &str) -> Option<&HookAction> {
+        self.executables
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, action)| action)
+    }
+
     pub fn add(&mut self, name: String, path: PathBuf) {
-        self.executables.push((name, path));
+        self.add_action(name, HookAction::Executable(path));
     }
+
+    fn add_action(&mut self, name: String, action: HookAction) {
+        match self.executables.iter_mut().find(|(n, _)| *n == name) {
+            Some(existing) => existing.1 = action,
+            None => self.executables.push((name, action)),
+        }
+    }
+
+    fn extend(&mut self, other: Hook) {
+        for (name, action) in other.executables {
+            self.add_action(name, action);
+        }
+    }
+}
+
+/// Splits off a numeric prefix, so that `9xrdb` sorts before `10i3`
+fn order_key(name: &str) -> (Option<u64>, &str) {
+    let digits = name.len() - name.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    (name[..digits].parse().ok(), &name[digits..])
+}
+
+/// Orders hooks into batches where every hook only depends on hooks of earlier batches.
+///
+/// Hooks with numeric prefixes run in the order of their prefixes, unprefixed ones don't depend
+/// on them. Within a batch, hooks are sorted by prefix and name. Returns indices into `names`.
+fn schedule(names: &[&str], config: &HookConfig) -> Vec<Vec<usize>> {
+    let runs_before = |a: usize, b: usize| {
+        let (key_a, key_b) = (order_key(names[a]), order_key(names[b]));
+        matches!((key_a.0, key_b.0), (Some(pa), Some(pb)) if pa < pb)
+            || config
+                .for_hook(names[a])
+                .before
+                .iter()
+                .any(|name| name == names[b])
+            || config
+                .for_hook(names[b])
+                .after
+                .iter()
+                .any(|name| name == names[a])
+    };
+
+    let mut remaining = (0..names.len()).collect::<Vec<_>>();
+    remaining.sort_by_key(|&i| (order_key(names[i]), names[i]));
+
+    let mut batches = Vec::new();
+    while !remaining.is_empty() {
+        let (ready, blocked): (Vec<_>, Vec<_>) = remaining
+            .iter()
+            .partition(|&&b| !remaining.iter().any(|&a| a != b && runs_before(a, b)));
+
+        if ready.is_empty() {
+            let cycle = blocked.iter().map(|&i| names[i]).collect::<Vec<_>>();
+            warn!(
+                "Hooks {:?} depend on each other, running them in name order",
+                cycle
+            );
+            batches.extend(blocked.into_iter().map(|i| vec![i]));
+            break;
+        }
+
+        batches.push(ready);
+        remaining = blocked;
+    }
+
+    batches
 }
 
-pub fn read_from(dir: &Path) -> HookSet {
+/// What to run when a file changes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OnChange<'a> {
+    /// A shell command
+    Command(&'a str),
+    /// An `onchange` hook
+    Hook {
+        name: &'a str,
+        action: &'a HookAction,
+    },
+}
+
+/// Runs the actions of changed files, applying the failure policy to each
+pub fn run_on_change(
+    actions: &[OnChange],
+    theme_dir: &Path,
+    theme_name: &str,
+    config: &HookConfig,
+    context: &HookContext,
+) -> Result<(), Error> {
+    for action in actions {
+        trace!("Running on change action {:?}", action);
+
+        let (executable, command) = match action {
+            OnChange::Command(command) => (
+                command.to_string(),
+                CommandLine::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .envs(context.env.vars()),
+            ),
+            OnChange::Hook { name, action } => (
+                name.to_string(),
+                action.command(theme_dir, theme_name, &context.env),
+            ),
+        };
+        run_recorded(
+            "Onchange",
+            &executable,
+            &command,
+            config.for_hook(&executable),
+            context,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Runs a command with the settings of the hook, records how it went and applies the failure
+/// policy
+fn run_recorded(
+    hook: &str,
+    executable: &str,
+    command: &CommandLine,
+    config: &HookConfig,
+    context: &HookContext,
+) -> Result<(), Error> {
+    let options = RunOptions {
+        timeout: config.timeout,
+        output_prefix: if config.capture {
+            Some(String::from(executable))
+        } else {
+            None
+        },
+    };
+    record(hook, executable, config, context.summary, || {
+        context.runner.run(command, &options)
+    })
+}
+
+/// Records how `run` went in the summary and applies the failure policy
+pub fn record(
+    hook: &str,
+    executable: &str,
+    config: &HookConfig,
+    summary: &HookSummary,
+    run: impl FnOnce() -> Result<(), String>,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    let res = run();
+    summary.push(HookRun {
+        hook: String::from(hook),
+        executable: String::from(executable),
+        elapsed: start.elapsed(),
+        error: res.as_ref().err().cloned(),
+        on_failure: config.on_failure,
+    });
+
+    let res = res.map_err(|cause| Error::Hook {
+        name: String::from(hook),
+        executable: String::from(executable),
+        cause,
+    });
+    match (res, config.on_failure) {
+        (Ok(()), _) => Ok(()),
+        (Err(e), FailurePolicy::Abort) => Err(e),
+        (Err(e), FailurePolicy::Warn) => {
+            warn!("{}", e);
+            Ok(())
+        }
+        (Err(e), FailurePolicy::Ignore) => {
+            trace!("Ignoring failure: {}", e);
+            Ok(())
+        }
+    }
+}
+
+/// Hook executables run in their own directory and get the theme as arguments
+fn executable_command(
+    executable: &Path,
+    theme_dir: &Path,
+    theme_name: &str,
+    env: &HookEnv,
+) -> CommandLine {
+    CommandLine::new(executable)
+        .current_dir(
+            executable
+                .parent()
+                .expect("Hook path does not have a parent. This is probably a bug"),
+        )
+        .arg(theme_dir)
+        .arg(theme_name)
+        .envs(env.vars())
+}
+
+/// Reads global hooks from every layer
+pub fn read_layers(fs: &dyn Fs, dirs: &[PathBuf], report: &mut Report) -> HookSet {
+    let mut hooks = HookSet::global();
+    for dir in dirs {
+        hooks.extend(read_from(fs, dir, report));
+    }
+
+    hooks
+}
+
+pub fn read_from(fs: &dyn Fs, dir: &Path, report: &mut Report) -> HookSet {
     trace!("Reading global hooks from {:?}", dir);
 
     let mut hooks = HookSet::global();
@@ -154,7 +673,7 @@ pub fn read_from(dir: &Path) -> HookSet {
         TreeReaderNode::Any,
         TreeReaderNode::Any,
     ];
-    for entry in TreeReader::new(dir, hooks_desc).get_file_entries_recursive() {
+    for entry in TreeReader::new(fs, dir, hooks_desc).get_file_entries_recursive() {
         assert_eq!(entry.captures.0.len(), 2);
         let mut captures = entry.captures.0;
 
@@ -168,12 +687,239 @@ pub fn read_from(dir: &Path) -> HookSet {
             "postinstall" => hooks.postinstall.add(hook_name, entry.path),
             "preremove" => hooks.preremove.add(hook_name, entry.path),
             "postremove" => hooks.postremove.add(hook_name, entry.path),
-            _ => warn!(
-                "Hook set '{}' is invalid. Hook will be ignored",
-                hook_set_name
+            "onchange" => hooks.onchange.add(hook_name, entry.path),
+            _ => report.warning(
+                entry.path,
+                format!(
+                    "Hook set '{}' is invalid. Hook will be ignored",
+                    hook_set_name
+                ),
             ),
         }
     }
 
     hooks
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::RecordingRunner;
+
+    fn hook(names: &[&str]) -> Hook {
+        let mut hook = HookSet::default().postinstall;
+        for name in names {
+            hook.add(String::from(*name), Path::new("/hooks").join(name));
+        }
+
+        hook
+    }
+
+    fn run(
+        hook: &Hook,
+        config: &HookConfig,
+        runner: &RecordingRunner,
+    ) -> (Result<(), Error>, usize) {
+        let summary = HookSummary::default();
+        let context = HookContext {
+            env: HookEnv::default().for_phase("postinstall", &[PathBuf::from("/out/a.conf")]),
+            summary: &summary,
+            runner,
+        };
+        let res = hook.run(Path::new("/themes/dark"), "dark", config, &context);
+
+        (res, summary.num_failed())
+    }
+
+    fn programs(runner: &RecordingRunner) -> Vec<String> {
+        runner
+            .argv()
+            .into_iter()
+            .map(|argv| argv[0].clone())
+            .collect()
+    }
+
+    #[test]
+    fn runs_in_schedule_order() {
+        let runner = RecordingRunner::default();
+        let (res, _) = run(&hook(&["20b", "10a", "c"]), &HookConfig::default(), &runner);
+
+        res.unwrap();
+        assert_eq!(
+            programs(&runner),
+            vec!["/hooks/c", "/hooks/10a", "/hooks/20b"]
+        );
+    }
+
+    #[test]
+    fn failure_policies() {
+        let runner = RecordingRunner {
+            failing: vec![String::from("/hooks/a")],
+            ..Default::default()
+        };
+        let (res, failed) = run(&hook(&["a", "b"]), &HookConfig::default(), &runner);
+        assert!(res.is_err());
+        assert_eq!(failed, 1);
+        assert_eq!(programs(&runner), vec!["/hooks/a"]);
+
+        let runner = RecordingRunner {
+            calls: Default::default(),
+            ..runner
+        };
+        let mut config = HookConfig::default();
+        config.named.insert(
+            String::from("a"),
+            HookConfig {
+                on_failure: FailurePolicy::Warn,
+                ..Default::default()
+            },
+        );
+        let (res, failed) = run(&hook(&["a", "b"]), &config, &runner);
+        res.unwrap();
+        assert_eq!(failed, 1);
+        assert_eq!(programs(&runner), vec!["/hooks/a", "/hooks/b"]);
+    }
+
+    #[test]
+    fn arguments_and_environment() {
+        let mut hook = hook(&["reload"]);
+        hook.add_action(
+            String::from("inline"),
+            HookAction::Inline(InlineHook {
+                name: String::from("inline"),
+                command: HookCommand::Shell(String::from("echo $FOO")),
+                cwd: Some(PathBuf::from("scripts")),
+                env: vec![(String::from("FOO"), String::from("bar"))]
+                    .into_iter()
+                    .collect(),
+                dir: PathBuf::from("/themes/dark"),
+            }),
+        );
+        let runner = RecordingRunner::default();
+        run(&hook, &HookConfig::default(), &runner).0.unwrap();
+
+        assert_eq!(
+            runner.argv()[..],
+            [
+                vec!["sh", "-c", "echo $FOO"],
+                vec!["/hooks/reload", "/themes/dark", "dark"]
+            ]
+        );
+        let calls = runner.calls.lock().unwrap();
+        let env = |i: usize, name: &str| {
+            calls[i]
+                .env
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        assert_eq!(calls[1].cwd.as_deref(), Some(Path::new("/hooks")));
+        assert_eq!(env(1, "TM_PHASE").as_deref(), Some("postinstall"));
+        assert_eq!(env(1, "TM_CHANGED_FILES").as_deref(), Some("/out/a.conf"));
+        assert_eq!(
+            calls[0].cwd.as_deref(),
+            Some(Path::new("/themes/dark/scripts"))
+        );
+        assert_eq!(env(0, "FOO").as_deref(), Some("bar"));
+    }
+
+    fn named(names: &[&str], schedule: Vec<Vec<usize>>) -> Vec<Vec<String>> {
+        schedule
+            .into_iter()
+            .map(|batch| batch.into_iter().map(|i| String::from(names[i])).collect())
+            .collect()
+    }
+
+    #[test]
+    fn numeric_prefixes() {
+        let names = ["10i3", "reload", "9xrdb", "10kitty"];
+        assert_eq!(
+            named(&names, schedule(&names, &HookConfig::default())),
+            vec![vec!["reload", "9xrdb"], vec!["10i3", "10kitty"]]
+        );
+    }
+
+    #[test]
+    fn dependencies() {
+        let mut config = HookConfig::default();
+        config.named.insert(
+            String::from("i3"),
+            HookConfig {
+                after: vec![String::from("xrdb")],
+                ..Default::default()
+            },
+        );
+        config.named.insert(
+            String::from("xrdb"),
+            HookConfig {
+                after: vec![String::from("gtk")],
+                ..Default::default()
+            },
+        );
+
+        let names = ["i3", "kitty", "xrdb", "gtk"];
+        assert_eq!(
+            named(&names, schedule(&names, &config)),
+            vec![vec!["gtk", "kitty"], vec!["xrdb"], vec!["i3"]]
+        );
+    }
+
+    #[test]
+    fn cycle() {
+        let mut config = HookConfig::default();
+        config.named.insert(
+            String::from("a"),
+            HookConfig {
+                after: vec![String::from("b")],
+                ..Default::default()
+            },
+        );
+        config.named.insert(
+            String::from("b"),
+            HookConfig {
+                after: vec![String::from("a")],
+                ..Default::default()
+            },
+        );
+
+        let names = ["b", "c", "a"];
+        assert_eq!(
+            named(&names, schedule(&names, &config)),
+            vec![vec!["c"], vec!["a"], vec!["b"]]
+        );
+    }
+
+    #[test]
+    fn inline_hooks() {
+        let hooks: InlineHooks = toml::de::from_str(
+            r#"
+            [[postinstall]]
+            name = "kitty"
+            command = "pkill -USR1 kitty"
+
+            [[postinstall]]
+            name = "i3"
+            command = ["i3-msg", "reload"]
+            cwd = "scripts"
+            env = { DISPLAY = ":0" }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            hooks.postinstall[0].command,
+            HookCommand::Shell(String::from("pkill -USR1 kitty"))
+        );
+        assert_eq!(
+            hooks.postinstall[1].command,
+            HookCommand::Argv(vec![String::from("i3-msg"), String::from("reload")])
+        );
+        assert_eq!(hooks.postinstall[1].env["DISPLAY"], ":0");
+
+        assert!(
+            toml::de::from_str::<InlineHooks>("[[postinstall]]\nname = \"x\"\ncommand = []\n")
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,178 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::{Color, Importer, Palette};
+use crate::fs::Fs;
+use crate::prelude::*;
+
+pub struct Alacritty;
+
+impl Importer for Alacritty {
+    fn name(&self) -> &'static str {
+        "alacritty"
+    }
+
+    fn read_palette(&self, fs: &dyn Fs, path: &Path) -> Result<Palette, Error> {
+        let data = fs
+            .read_to_string(path)
+            .context("Could not read alacritty config")?;
+        let is_toml = path.extension().map(|ext| ext == "toml").unwrap_or(false);
+        parse(&data, is_toml)
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    #[serde(default)]
+    colors: Colors,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Colors {
+    #[serde(default)]
+    primary: Primary,
+    #[serde(default)]
+    cursor: Cursor,
+    #[serde(default)]
+    selection: Selection,
+    #[serde(default)]
+    normal: Ansi,
+    #[serde(default)]
+    bright: Ansi,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Primary {
+    foreground: Option<String>,
+    background: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Cursor {
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Selection {
+    background: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Ansi {
+    black: Option<String>,
+    red: Option<String>,
+    green: Option<String>,
+    yellow: Option<String>,
+    blue: Option<String>,
+    magenta: Option<String>,
+    cyan: Option<String>,
+    white: Option<String>,
+}
+
+impl Ansi {
+    fn colors(&self) -> [&Option<String>; 8] {
+        [
+            &self.black,
+            &self.red,
+            &self.green,
+            &self.yellow,
+            &self.blue,
+            &self.magenta,
+            &self.cyan,
+            &self.white,
+        ]
+    }
+}
+
+/// Parses the `colors` section of an alacritty config, either in YAML or TOML
+pub fn parse(data: &str, is_toml: bool) -> Result<Palette, Error> {
+    let config: Config = if is_toml {
+        toml::de::from_str(data).context("Could not parse alacritty config")?
+    } else {
+        serde_yaml::from_str(data).context("Could not parse alacritty config")?
+    };
+    let colors = config.colors;
+
+    let color = |value: &Option<String>| -> Result<Option<Color>, Error> {
+        match value {
+            // Colors taken from the cell under the cursor or selection have no fixed value
+            Some(value) if value == "CellForeground" || value == "CellBackground" => Ok(None),
+            Some(value) => {
+                let hex = value.strip_prefix("0x").unwrap_or(value);
+                Color::from_hex(hex).map(Some).ok_or_else(|| {
+                    Error::InvalidScheme(format!("'{}' is not a valid color", value))
+                })
+            }
+            None => Ok(None),
+        }
+    };
+
+    let mut palette = Palette {
+        foreground: color(&colors.primary.foreground)?,
+        background: color(&colors.primary.background)?,
+        cursor: color(&colors.cursor.cursor)?,
+        selection: color(&colors.selection.background)?,
+        ..Default::default()
+    };
+    let ansi = colors
+        .normal
+        .colors()
+        .iter()
+        .chain(colors.bright.colors().iter())
+        .map(|value| color(value))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (slot, value) in palette.ansi.iter_mut().zip(ansi) {
+        *slot = value;
+    }
+
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_yaml() {
+        let palette = parse(
+            "colors:\n  primary:\n    background: '0x1d2021'\n    foreground: '#d5c4a1'\n  \
+             normal:\n    red: '0xfb4934'\n  bright:\n    white: '0xfbf1c7'\n",
+            false,
+        )
+        .unwrap();
+
+        assert_eq!(palette.background, Color::from_hex("1d2021"));
+        assert_eq!(palette.foreground, Color::from_hex("d5c4a1"));
+        assert_eq!(palette.ansi[1], Color::from_hex("fb4934"));
+        assert_eq!(palette.ansi[15], Color::from_hex("fbf1c7"));
+    }
+
+    #[test]
+    fn parse_toml() {
+        let palette = parse(
+            "[colors.primary]\nbackground = \"#1d2021\"\n\n\
+             [colors.cursor]\ntext = \"#000000\"\ncursor = \"#ffffff\"\n",
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(palette.background, Color::from_hex("1d2021"));
+        assert_eq!(palette.cursor, Color::from_hex("ffffff"));
+    }
+
+    #[test]
+    fn cell_colors() {
+        let palette = parse(
+            "[colors.primary]\nbackground = \"#1d2021\"\n\n\
+             [colors.cursor]\ntext = \"CellBackground\"\ncursor = \"CellForeground\"\n\n\
+             [colors.selection]\ntext = \"CellBackground\"\nbackground = \"CellForeground\"\n",
+            true,
+        )
+        .unwrap();
+
+        assert_eq!(palette.background, Color::from_hex("1d2021"));
+        assert_eq!(palette.cursor, None);
+        assert_eq!(palette.selection, None);
+    }
+}
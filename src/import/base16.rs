@@ -0,0 +1,262 @@
+use std::{collections::HashMap, path::Path};
+
+use super::{Color, Importer, Palette};
+use crate::fs::Fs;
+use crate::prelude::*;
+
+pub const BASE16: &[&str] = &[
+    "base00", "base01", "base02", "base03", "base04", "base05", "base06", "base07", "base08",
+    "base09", "base0A", "base0B", "base0C", "base0D", "base0E", "base0F",
+];
+
+const BASE24: &[&str] = &[
+    "base10", "base11", "base12", "base13", "base14", "base15", "base16", "base17",
+];
+
+/// A base16 or base24 color scheme
+#[derive(Debug)]
+pub struct Scheme {
+    pub name: String,
+    pub author: Option<String>,
+    pub slug: String,
+    pub variant: Option<String>,
+    pub colors: Vec<(String, Color)>,
+}
+
+impl Scheme {
+    pub fn system(&self) -> &'static str {
+        if self.colors.len() > BASE16.len() {
+            "base24"
+        } else {
+            "base16"
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<Color> {
+        self.colors
+            .iter()
+            .find(|(color_name, _)| color_name == name)
+            .map(|(_, color)| *color)
+    }
+
+    /// Maps the scheme to terminal colors the same way base16-shell does.
+    ///
+    /// The base16 values are kept as extra values, so base16 templates can use the palette too.
+    pub fn palette(&self) -> Palette {
+        let color = |name: &str| self.get(name);
+        let ansi = [
+            "base00", "base08", "base0B", "base0A", "base0D", "base0E", "base0C", "base05",
+            "base03", "base08", "base0B", "base0A", "base0D", "base0E", "base0C", "base07",
+        ];
+
+        let mut palette = Palette {
+            name: Some(self.name.clone()),
+            author: self.author.clone(),
+            foreground: color("base05"),
+            background: color("base00"),
+            cursor: color("base05"),
+            selection: color("base02"),
+            extra: self.unit_values(),
+            ..Default::default()
+        };
+        for (i, name) in ansi.iter().enumerate() {
+            palette.ansi[i] = color(name);
+        }
+
+        palette
+    }
+
+    /// Values in the same form base16 templates expect
+    pub fn unit_values(&self) -> HashMap<String, String> {
+        let mut values = HashMap::new();
+        values.insert(String::from("scheme-name"), self.name.clone());
+        values.insert(String::from("scheme-slug"), self.slug.clone());
+        values.insert(String::from("scheme-system"), String::from(self.system()));
+        if let Some(ref author) = self.author {
+            values.insert(String::from("scheme-author"), author.clone());
+        }
+        if let Some(ref variant) = self.variant {
+            values.insert(String::from("scheme-variant"), variant.clone());
+        }
+
+        for (name, color) in &self.colors {
+            let hex = color.hex();
+            let components = [
+                ("r", color.r, &hex[0..2]),
+                ("g", color.g, &hex[2..4]),
+                ("b", color.b, &hex[4..6]),
+            ];
+
+            values.insert(name.clone(), color.to_string());
+            values.insert(format!("{}-hex", name), hex.clone());
+            values.insert(
+                format!("{}-hex-bgr", name),
+                format!("{}{}{}", &hex[4..6], &hex[2..4], &hex[0..2]),
+            );
+            for (component, value, hex) in &components {
+                values.insert(format!("{}-hex-{}", name, component), String::from(*hex));
+                values.insert(format!("{}-rgb-{}", name, component), value.to_string());
+                values.insert(
+                    format!("{}-dec-{}", name, component),
+                    format!("{:.8}", *value as f64 / 255.0),
+                );
+            }
+        }
+
+        values
+    }
+}
+
+/// Reads a scheme in either the classic format with colors at the top level,
+/// or the newer one with colors under `palette`
+pub fn read_scheme(fs: &dyn Fs, path: &Path) -> Result<Scheme, Error> {
+    let data = fs
+        .read_to_string(path)
+        .context("Could not read scheme file")?;
+    let mut fields: HashMap<String, serde_yaml::Value> =
+        serde_yaml::from_str(&data).context("Could not parse scheme file")?;
+
+    let mut colors = fields.clone();
+    if let Some(palette) = fields.remove("palette") {
+        colors = serde_yaml::from_value(palette).context("Could not parse scheme palette")?;
+    }
+
+    let string = |key: &str| {
+        fields
+            .get(key)
+            .and_then(|value| value.as_str())
+            .map(String::from)
+    };
+    let color = |key: &str| -> Result<Option<Color>, Error> {
+        match colors.get(key) {
+            Some(value) => value
+                .as_str()
+                .and_then(Color::from_hex)
+                .map(Some)
+                .ok_or_else(|| Error::InvalidScheme(format!("'{}' is not a valid color", key))),
+            None => Ok(None),
+        }
+    };
+
+    let mut scheme_colors = Vec::new();
+    for name in BASE16 {
+        let value =
+            color(name)?.ok_or_else(|| Error::InvalidScheme(format!("'{}' is missing", name)))?;
+        scheme_colors.push((String::from(*name), value));
+    }
+    for name in BASE24 {
+        if let Some(value) = color(name)? {
+            scheme_colors.push((String::from(*name), value));
+        }
+    }
+
+    let slug = string("slug").unwrap_or_else(|| {
+        path.file_stem()
+            .and_then(|stem| stem.to_str())
+            .map(String::from)
+            .unwrap_or_default()
+    });
+
+    Ok(Scheme {
+        name: string("scheme")
+            .or_else(|| string("name"))
+            .unwrap_or_else(|| slug.clone()),
+        author: string("author"),
+        slug,
+        variant: string("variant"),
+        colors: scheme_colors,
+    })
+}
+
+pub struct Base16;
+
+impl Importer for Base16 {
+    fn name(&self) -> &'static str {
+        "base16"
+    }
+
+    fn read_palette(&self, fs: &dyn Fs, path: &Path) -> Result<Palette, Error> {
+        Ok(read_scheme(fs, path)?.palette())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+
+    const SCHEME: &str = r##"
+scheme: "Test Scheme"
+author: "Someone"
+base00: "1d2021"
+base01: "3c3836"
+base02: "504945"
+base03: "665c54"
+base04: "bdae93"
+base05: "d5c4a1"
+base06: "ebdbb2"
+base07: "fbf1c7"
+base08: "fb4934"
+base09: "fe8019"
+base0A: "fabd2f"
+base0B: "b8bb26"
+base0C: "8ec07c"
+base0D: "83a598"
+base0E: "d3869b"
+base0F: "d65d0e"
+"##;
+
+    fn read(data: &str) -> Result<Scheme, Error> {
+        let fs = MemoryFs::with_files([("/scheme.yaml", data)]);
+        read_scheme(&fs, Path::new("/scheme.yaml"))
+    }
+
+    #[test]
+    fn classic_scheme() {
+        let scheme = read(SCHEME).unwrap();
+
+        assert_eq!(scheme.name, "Test Scheme");
+        assert_eq!(scheme.system(), "base16");
+        assert_eq!(scheme.get("base0D"), Color::from_hex("83a598"));
+
+        let values = scheme.unit_values();
+        assert_eq!(values["base08"], "#fb4934");
+        assert_eq!(values["base08-hex"], "fb4934");
+        assert_eq!(values["base08-hex-bgr"], "3449fb");
+        assert_eq!(values["base08-rgb-r"], "251");
+        assert_eq!(values["base08-hex-b"], "34");
+        assert_eq!(values["scheme-author"], "Someone");
+
+        let palette = scheme.palette();
+        assert_eq!(palette.ansi[1], Color::from_hex("fb4934"));
+        assert_eq!(palette.background, Color::from_hex("1d2021"));
+        assert_eq!(palette.unit_values()["base0D"], "#83a598");
+    }
+
+    #[test]
+    fn palette_scheme() {
+        let palette = SCHEME
+            .lines()
+            .map(|line| {
+                if line.starts_with("base") {
+                    format!("  {}", line)
+                } else {
+                    String::from(line)
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+            .replace("author: \"Someone\"", "author: \"Someone\"\npalette:")
+            + "\n  base10: \"#000000\"\n";
+
+        let scheme = read(&palette).unwrap();
+
+        assert_eq!(scheme.system(), "base24");
+        assert_eq!(scheme.get("base10"), Color::from_hex("000000"));
+    }
+
+    #[test]
+    fn missing_color() {
+        assert!(read(&SCHEME.replace("base0F: \"d65d0e\"", "")).is_err());
+    }
+}
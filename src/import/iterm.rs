@@ -0,0 +1,120 @@
+use std::path::Path;
+
+use regex::Regex;
+
+use super::{Color, Importer, Palette};
+use crate::fs::Fs;
+use crate::prelude::*;
+
+pub struct Iterm;
+
+impl Importer for Iterm {
+    fn name(&self) -> &'static str {
+        "iterm"
+    }
+
+    fn read_palette(&self, fs: &dyn Fs, path: &Path) -> Result<Palette, Error> {
+        let data = fs
+            .read_to_string(path)
+            .context("Could not read itermcolors file")?;
+        parse(&data)
+    }
+}
+
+/// Parses the plist of an `.itermcolors` file.
+///
+/// Every color is a flat dictionary of real components, so the plist is matched with
+/// regular expressions rather than parsed in full.
+pub fn parse(data: &str) -> Result<Palette, Error> {
+    let color_re = Regex::new(r"(?s)<key>([^<]*)</key>\s*<dict>(.*?)</dict>").unwrap();
+    let component_re =
+        Regex::new(r"(?s)<key>(Red|Green|Blue) Component</key>\s*<real>([^<]*)</real>").unwrap();
+
+    let mut palette = Palette::default();
+    for color in color_re.captures_iter(data) {
+        let key = &color[1];
+        let slot = match key {
+            "Foreground Color" => &mut palette.foreground,
+            "Background Color" => &mut palette.background,
+            "Cursor Color" => &mut palette.cursor,
+            "Selection Color" => &mut palette.selection,
+            _ => match key
+                .strip_prefix("Ansi ")
+                .and_then(|key| key.strip_suffix(" Color"))
+                .and_then(|i| i.parse::<usize>().ok())
+                .and_then(|i| palette.ansi.get_mut(i))
+            {
+                Some(slot) => slot,
+                None => continue,
+            },
+        };
+
+        let (mut r, mut g, mut b) = (None, None, None);
+        for component in component_re.captures_iter(&color[2]) {
+            let value = component[2].trim().parse::<f64>().map_err(|_| {
+                Error::InvalidScheme(format!("Invalid component value in '{}'", key))
+            })?;
+            let value = Some((value.clamp(0.0, 1.0) * 255.0).round() as u8);
+            match &component[1] {
+                "Red" => r = value,
+                "Green" => g = value,
+                _ => b = value,
+            }
+        }
+
+        match (r, g, b) {
+            (Some(r), Some(g), Some(b)) => *slot = Some(Color { r, g, b }),
+            _ => {
+                return Err(Error::InvalidScheme(format!(
+                    "'{}' is missing a component",
+                    key
+                )))
+            }
+        }
+    }
+
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_plist() {
+        let palette = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<dict>
+	<key>Ansi 1 Color</key>
+	<dict>
+		<key>Alpha Component</key>
+		<real>1</real>
+		<key>Blue Component</key>
+		<real>0.0</real>
+		<key>Color Space</key>
+		<string>sRGB</string>
+		<key>Green Component</key>
+		<real>0.5</real>
+		<key>Red Component</key>
+		<real>1</real>
+	</dict>
+	<key>Background Color</key>
+	<dict>
+		<key>Blue Component</key>
+		<real>0.12941176</real>
+		<key>Green Component</key>
+		<real>0.12549020</real>
+		<key>Red Component</key>
+		<real>0.11372549</real>
+	</dict>
+</dict>
+</plist>"#,
+        )
+        .unwrap();
+
+        assert_eq!(palette.ansi[1], Color::from_hex("ff8000"));
+        assert_eq!(palette.background, Color::from_hex("1d2021"));
+        assert_eq!(palette.foreground, None);
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use crate::fs::Fs;
+use crate::prelude::*;
+use crate::scaffold;
+use crate::themes::ThemeOptions;
+
+pub mod alacritty;
+pub mod base16;
+pub mod iterm;
+pub mod xresources;
+
+/// Parses a color scheme format into a palette
+pub trait Importer {
+    /// Name used to select the importer on the command line
+    fn name(&self) -> &'static str;
+    fn read_palette(&self, fs: &dyn Fs, path: &Path) -> Result<Palette, Error>;
+}
+
+pub const IMPORTERS: &[&dyn Importer] = &[
+    &base16::Base16,
+    &xresources::Xresources,
+    &iterm::Iterm,
+    &alacritty::Alacritty,
+];
+
+pub fn find_importer(name: &str) -> Option<&'static dyn Importer> {
+    IMPORTERS
+        .iter()
+        .find(|importer| importer.name() == name)
+        .copied()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    /// Parses `rrggbb`, with or without a leading `#`
+    pub fn from_hex(s: &str) -> Option<Color> {
+        let s = s.trim();
+        let s = s.strip_prefix('#').unwrap_or(s);
+        if s.len() != 6 || !s.is_ascii() {
+            return None;
+        }
+
+        let component = |i: usize| u8::from_str_radix(&s[i..i + 2], 16).ok();
+        Some(Color {
+            r: component(0)?,
+            g: component(2)?,
+            b: component(4)?,
+        })
+    }
+
+    /// `rrggbb` without a leading `#`
+    pub fn hex(&self) -> String {
+        format!("{:02x}{:02x}{:02x}", self.r, self.g, self.b)
+    }
+}
+
+impl fmt::Display for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.hex())
+    }
+}
+
+/// Colors every imported scheme is converted to
+#[derive(Debug, Default)]
+pub struct Palette {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub ansi: [Option<Color>; 16],
+    pub foreground: Option<Color>,
+    pub background: Option<Color>,
+    pub cursor: Option<Color>,
+    pub selection: Option<Color>,
+    /// Format-specific values, such as the base16 colors
+    pub extra: HashMap<String, String>,
+}
+
+impl Palette {
+    /// Values as they appear in the palette unit: `color0`..`color15`, `foreground`,
+    /// `background`, `cursor` and `selection`, along with any format-specific values
+    pub fn unit_values(&self) -> HashMap<String, String> {
+        let mut values = self.extra.clone();
+
+        let named = self
+            .ansi
+            .iter()
+            .enumerate()
+            .map(|(i, color)| (format!("color{}", i), color))
+            .chain(vec![
+                (String::from("foreground"), &self.foreground),
+                (String::from("background"), &self.background),
+                (String::from("cursor"), &self.cursor),
+                (String::from("selection"), &self.selection),
+            ]);
+        for (name, color) in named {
+            if let Some(color) = color {
+                values.insert(name, color.to_string());
+            }
+        }
+
+        values
+    }
+
+    /// Reads the colors back from values in the form `unit_values` produces
+    pub fn from_values<'a>(values: impl IntoIterator<Item = (&'a String, &'a String)>) -> Palette {
+        let mut palette = Palette::default();
+        for (name, value) in values {
+            let slot = match name.as_str() {
+                "foreground" => &mut palette.foreground,
+                "background" => &mut palette.background,
+                "cursor" => &mut palette.cursor,
+                "selection" => &mut palette.selection,
+                _ => match name
+                    .strip_prefix("color")
+                    .and_then(|i| i.parse::<usize>().ok())
+                    .and_then(|i| palette.ansi.get_mut(i))
+                {
+                    Some(slot) => slot,
+                    None => continue,
+                },
+            };
+            *slot = Color::from_hex(value);
+        }
+
+        palette
+    }
+
+    fn missing(&self) -> Vec<String> {
+        let mut missing = self
+            .ansi
+            .iter()
+            .enumerate()
+            .filter(|(_, color)| color.is_none())
+            .map(|(i, _)| format!("color{}", i))
+            .collect::<Vec<_>>();
+        if self.foreground.is_none() {
+            missing.push(String::from("foreground"));
+        }
+        if self.background.is_none() {
+            missing.push(String::from("background"));
+        }
+
+        missing
+    }
+}
+
+/// Turns a scheme name into something usable as a theme name
+pub fn theme_name(name: &str) -> String {
+    name.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Creates a theme directory with a compound unit file for each unit
+pub fn write_theme(
+    fs: &dyn Fs,
+    dir: &Path,
+    name: &str,
+    options: &ThemeOptions,
+    units: &[(&str, HashMap<String, String>)],
+) -> Result<PathBuf, Error> {
+    let units = units
+        .iter()
+        .map(|(unit_name, values)| Ok((String::from(*unit_name), scaffold::unit_data(values)?)))
+        .collect::<Result<Vec<_>, Error>>()?;
+
+    scaffold::create_theme_dir(fs, dir, name, options, &units)
+}
+
+/// Creates a theme from a scheme file, with the palette in a single unit
+pub fn import(
+    fs: &dyn Fs,
+    dir: &Path,
+    importer: &dyn Importer,
+    path: &Path,
+    name: Option<&str>,
+    unit_name: &str,
+) -> Result<PathBuf, Error> {
+    let palette = importer
+        .read_palette(fs, path)
+        .with_context(|| format!("Could not import {:?}", path))?;
+
+    let missing = palette.missing();
+    if !missing.is_empty() {
+        warn!("{:?} does not define {}", path, missing.join(", "));
+    }
+
+    let name = match name {
+        Some(name) => String::from(name),
+        None => {
+            let stem = path.file_stem().and_then(|stem| stem.to_str());
+            theme_name(stem.or(palette.name.as_deref()).unwrap_or_default())
+        }
+    };
+    let options = ThemeOptions {
+        author: palette.author.clone(),
+        description: palette.name.clone(),
+        ..Default::default()
+    };
+
+    write_theme(
+        fs,
+        dir,
+        &name,
+        &options,
+        &[(unit_name, palette.unit_values())],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_colors() {
+        let color = Color {
+            r: 0x1d,
+            g: 0x20,
+            b: 0x21,
+        };
+        assert_eq!(Color::from_hex("1d2021"), Some(color));
+        assert_eq!(Color::from_hex("#1D2021"), Some(color));
+        assert_eq!(color.to_string(), "#1d2021");
+
+        assert_eq!(Color::from_hex("#1d202"), None);
+        assert_eq!(Color::from_hex("1d202g"), None);
+    }
+
+    #[test]
+    fn theme_names() {
+        assert_eq!(theme_name("Gruvbox dark, hard"), "gruvbox_dark_hard");
+        assert_eq!(theme_name("tomorrow-night"), "tomorrow_night");
+    }
+}
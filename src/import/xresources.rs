@@ -0,0 +1,121 @@
+use std::{collections::HashMap, path::Path};
+
+use super::{Color, Importer, Palette};
+use crate::fs::Fs;
+use crate::prelude::*;
+
+pub struct Xresources;
+
+impl Importer for Xresources {
+    fn name(&self) -> &'static str {
+        "xresources"
+    }
+
+    fn read_palette(&self, fs: &dyn Fs, path: &Path) -> Result<Palette, Error> {
+        let data = fs
+            .read_to_string(path)
+            .context("Could not read Xresources file")?;
+        parse(&data)
+    }
+}
+
+/// Parses resources like `*.color0: #000000` or `URxvt*background: rgb:00/00/00`,
+/// substituting `#define`d names
+pub fn parse(data: &str) -> Result<Palette, Error> {
+    let mut defines = HashMap::new();
+    let mut palette = Palette::default();
+
+    for line in data.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('!') {
+            continue;
+        }
+
+        if let Some(define) = line.strip_prefix("#define") {
+            let mut parts = define.split_whitespace();
+            if let (Some(name), Some(value)) = (parts.next(), parts.next()) {
+                defines.insert(name, value);
+            }
+            continue;
+        }
+
+        let (key, value) = match line.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        let resource = key.rsplit(['.', '*']).next().unwrap_or(key);
+        let value = defines.get(value).copied().unwrap_or(value);
+
+        let slot = match resource {
+            "foreground" => &mut palette.foreground,
+            "background" => &mut palette.background,
+            "cursorColor" => &mut palette.cursor,
+            "highlightColor" => &mut palette.selection,
+            _ => match resource
+                .strip_prefix("color")
+                .and_then(|i| i.parse::<usize>().ok())
+                .and_then(|i| palette.ansi.get_mut(i))
+            {
+                Some(slot) => slot,
+                None => continue,
+            },
+        };
+
+        let color = parse_color(value).ok_or_else(|| {
+            Error::InvalidScheme(format!("'{}' is not a valid color for {}", value, key))
+        })?;
+        *slot = Some(color);
+    }
+
+    Ok(palette)
+}
+
+fn parse_color(value: &str) -> Option<Color> {
+    match value.strip_prefix("rgb:") {
+        Some(rgb) => {
+            let mut components = rgb.split('/').map(|c| u8::from_str_radix(c, 16).ok());
+            let color = Color {
+                r: components.next()??,
+                g: components.next()??,
+                b: components.next()??,
+            };
+            if components.next().is_some() {
+                return None;
+            }
+            Some(color)
+        }
+        None => Color::from_hex(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_resources() {
+        let palette = parse(
+            "! comment\n\
+             #define bg #1d2021\n\
+             *.background: bg\n\
+             *foreground: #d5c4a1\n\
+             URxvt*cursorColor: rgb:d5/c4/a1\n\
+             *.color0: #1d2021\n\
+             *color15: #fbf1c7\n\
+             *.font: Monospace\n",
+        )
+        .unwrap();
+
+        assert_eq!(palette.background, Color::from_hex("1d2021"));
+        assert_eq!(palette.foreground, Color::from_hex("d5c4a1"));
+        assert_eq!(palette.cursor, Color::from_hex("d5c4a1"));
+        assert_eq!(palette.ansi[0], Color::from_hex("1d2021"));
+        assert_eq!(palette.ansi[15], Color::from_hex("fbf1c7"));
+        assert_eq!(palette.ansi[1], None);
+    }
+
+    #[test]
+    fn invalid_color() {
+        assert!(parse("*.color1: red\n").is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::fs::Fs;
+use crate::install::FileDescDeserialize;
+use crate::prelude::*;
+use crate::scaffold;
+use crate::themes::ThemeOptions;
+
+#[derive(Serialize)]
+struct InstallToml {
+    vars: BTreeMap<String, String>,
+    file: Vec<FileDescDeserialize>,
+}
+
+/// Creates a manager directory that installs the adopted files to where they currently are.
+///
+/// Adopted files are copied as they are and are not treated as templates,
+/// since existing configs may contain anything that looks like a mustache tag.
+/// Nothing is created unless every adopted path is a file.
+pub fn init_dir(fs: &dyn Fs, dir: &Path, adopt: &[PathBuf]) -> Result<(), Error> {
+    let install_dir = dir.join("install");
+    if fs.exists(&install_dir.join("install.toml")) {
+        return Err(Error::AlreadyInitialized(dir.to_owned()));
+    }
+
+    let vars = install_vars();
+    let mut names = HashSet::new();
+    let mut file_names = HashSet::new();
+    let mut files = Vec::new();
+    let mut copies = Vec::new();
+
+    for path in adopt {
+        // Symlinks are kept, so that targets of stowed dotfiles stay where the program looks
+        let path =
+            std::path::absolute(path).with_context(|| format!("Could not adopt {:?}", path))?;
+        if !fs.is_file(&path) {
+            return Err(Error::InvalidPath {}).with_context(|| format!("{:?} is not a file", path));
+        }
+
+        let name = unique_name(&path, &mut names)?;
+        let file_name = path.file_name().and_then(|name| name.to_str());
+        let file_name = match file_name {
+            Some(file_name) if file_names.insert(String::from(file_name)) => {
+                String::from(file_name)
+            }
+            _ => format!("{}{}", name, extension(&path)),
+        };
+        file_names.insert(file_name.clone());
+
+        trace!("Adopting {:?} as '{}'", path, name);
+
+        let stem = Path::new(&file_name)
+            .file_stem()
+            .and_then(|stem| stem.to_str());
+        files.push(FileDescDeserialize {
+            name: if stem == Some(name.as_str()) {
+                None
+            } else {
+                Some(name)
+            },
+            path: PathBuf::from(&file_name),
+            target: target_for(&path, &vars),
+            template: false,
+            units: Vec::new(),
+            profiles: Vec::new(),
+            on_change: Vec::new(),
+            on_change_hooks: Vec::new(),
+            reload: Vec::new(),
+        });
+        copies.push((path, install_dir.join(file_name)));
+    }
+
+    let install = toml::to_string(&InstallToml { vars, file: files })
+        .context("Could not serialize install.toml")?;
+
+    fs.create_dir_all(&install_dir)
+        .context("Could not create install directory")?;
+    fs.create_dir_all(&dir.join("themes"))
+        .context("Could not create themes directory")?;
+    for (path, copy) in copies {
+        fs.copy(&path, &copy)
+            .with_context(|| format!("Could not copy {:?}", path))?;
+    }
+    fs.write(&install_dir.join("install.toml"), install.as_bytes())
+        .context("Could not write install.toml")?;
+
+    scaffold::create_theme_dir(fs, dir, "default", &ThemeOptions::default(), &[])?;
+
+    Ok(())
+}
+
+fn install_vars() -> BTreeMap<String, String> {
+    let mut vars = BTreeMap::new();
+
+    if let Some(home) = std::env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        let config = std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| home.join(".config"));
+
+        vars.insert(
+            String::from("config"),
+            config.to_string_lossy().into_owned(),
+        );
+        vars.insert(String::from("home"), home.to_string_lossy().into_owned());
+    }
+
+    vars
+}
+
+/// Makes the target path relative to the most specific install var that contains it
+fn target_for(path: &Path, vars: &BTreeMap<String, String>) -> String {
+    let best = vars
+        .iter()
+        .filter_map(|(var, dir)| Some((var, path.strip_prefix(dir).ok()?)))
+        .min_by_key(|(_, rest)| rest.components().count());
+
+    match best {
+        Some((var, rest)) => format!("{{{{{}}}}}/{}", var, rest.display()),
+        None => path.display().to_string(),
+    }
+}
+
+/// Derives a unit name from the file name, prefixing the parent directory on collisions
+fn unique_name(path: &Path, names: &mut HashSet<String>) -> Result<String, Error> {
+    let stem = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or(Error::InvalidPath {})?;
+    let mut name = sanitize(stem);
+
+    if names.contains(&name) {
+        if let Some(parent) = path
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .and_then(|parent| parent.to_str())
+        {
+            name = format!("{}_{}", sanitize(parent), name);
+        }
+    }
+
+    let base = name.clone();
+    let mut i = 2;
+    while names.contains(&name) {
+        name = format!("{}_{}", base, i);
+        i += 1;
+    }
+
+    names.insert(name.clone());
+    Ok(name)
+}
+
+/// Unit names can't contain dashes or start with an underscore
+fn sanitize(s: &str) -> String {
+    let s = s
+        .trim_start_matches(['.', '_'])
+        .replace(|c: char| !c.is_alphanumeric() && c != '_', "_");
+    if s.is_empty() {
+        String::from("file")
+    } else {
+        s
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .map(|ext| format!(".{}", ext))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+
+    #[test]
+    fn targets() {
+        let vars = vec![
+            (String::from("home"), String::from("/home/user")),
+            (String::from("config"), String::from("/home/user/.config")),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(
+            target_for(Path::new("/home/user/.config/kitty/kitty.conf"), &vars),
+            "{{config}}/kitty/kitty.conf"
+        );
+        assert_eq!(
+            target_for(Path::new("/home/user/.Xresources"), &vars),
+            "{{home}}/.Xresources"
+        );
+        assert_eq!(target_for(Path::new("/etc/motd"), &vars), "/etc/motd");
+    }
+
+    #[test]
+    fn names() {
+        let mut names = HashSet::new();
+        let name =
+            |path: &str, names: &mut HashSet<String>| unique_name(Path::new(path), names).unwrap();
+
+        assert_eq!(name("/home/user/.config/i3/config", &mut names), "config");
+        assert_eq!(
+            name("/home/user/.config/sway/config", &mut names),
+            "sway_config"
+        );
+        assert_eq!(name("/home/user/.Xresources", &mut names), "Xresources");
+        assert_eq!(name("/home/user/user-dirs.dirs", &mut names), "user_dirs");
+    }
+
+    #[test]
+    fn bad_path_creates_nothing() {
+        let fs = MemoryFs::with_files([("/home/user/.config/kitty/kitty.conf", "font_size 11")]);
+        let adopt = |paths: &[&str]| {
+            let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+            init_dir(&fs, Path::new("/m"), &paths)
+        };
+
+        assert!(adopt(&[
+            "/home/user/.config/kitty/kitty.conf",
+            "/home/user/.config/kitty"
+        ])
+        .is_err());
+        assert!(!fs.exists(Path::new("/m")));
+
+        adopt(&["/home/user/.config/kitty/kitty.conf"]).unwrap();
+        assert_eq!(
+            fs.read_to_string(Path::new("/m/install/kitty.conf"))
+                .unwrap(),
+            "font_size 11"
+        );
+        assert!(fs.exists(Path::new("/m/install/install.toml")));
+        assert!(fs.is_dir(Path::new("/m/themes/default")));
+    }
+}
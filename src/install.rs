@@ -1,35 +1,103 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::{
     collections::HashMap,
     convert::TryFrom,
     path::{Path, PathBuf},
 };
 
-use crate::hooks::HookLauncher;
+use crate::command::CommandRunner;
+use crate::config::{BackupPolicy, Config};
+use crate::fs::Fs;
+use crate::hooks::{self, HookContext, HookEnv, HookLauncher, HookSummary, InlineHooks, OnChange};
 use crate::prelude::*;
+use crate::reload::{self, Reload, ReloadContext, ReloadTask};
 use crate::themes::ThemeDesc;
 
 fn get_true() -> bool {
     true
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct FileDescDeserialize {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub name: Option<String>,
     pub path: PathBuf,
     pub target: String,
     #[serde(default = "get_true")]
     pub template: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub units: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub profiles: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_change: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_change_hooks: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reload: Vec<Reload>,
 }
 
 #[derive(Debug, Deserialize)]
 #[serde(try_from = "FileDescDeserialize")]
 pub struct FileDesc {
+    /// Install directory of the layer the file was declared in
+    pub dir: PathBuf,
     pub name: String,
     pub path: PathBuf,
     pub target: String,
     pub template: bool,
+    /// Additional units whose values are available to the template, such as a shared palette
+    pub units: Vec<String>,
+    /// Profiles the file is installed in, all of them if empty
+    pub profiles: Vec<String>,
+    /// Shell commands run when the target changed
+    pub on_change: Vec<String>,
+    /// Names of `onchange` hooks run when the target changed
+    pub on_change_hooks: Vec<String>,
+    /// Built-in actions run when the target changed
+    pub reload: Vec<Reload>,
+}
+
+impl FileDesc {
+    /// Names of all units the template gets values from, in order of increasing priority
+    pub fn unit_names(&self) -> impl Iterator<Item = &str> {
+        self.units
+            .iter()
+            .map(|unit| unit.as_str())
+            .chain(std::iter::once(self.name.as_str()))
+    }
+
+    pub fn in_profile(&self, profile: Option<&str>) -> bool {
+        match profile {
+            Some(profile) if !self.profiles.is_empty() => {
+                self.profiles.iter().any(|p| p == profile)
+            }
+            _ => true,
+        }
+    }
+
+    pub fn uses_unit(&self, unit_name: &str) -> bool {
+        self.unit_names().any(|name| name == unit_name)
+    }
+
+    /// Collects the values for the template, later themes in the chain overriding earlier ones
+    pub fn values<'a>(&self, theme_chain: &[&'a ThemeDesc]) -> HashMap<&'a str, &'a str> {
+        let mut values = HashMap::new();
+        for theme in theme_chain {
+            for unit_name in self.unit_names() {
+                if let Some(theme_unit) = theme.units.get(unit_name) {
+                    values.extend(
+                        theme_unit
+                            .values
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value.as_str())),
+                    );
+                }
+            }
+        }
+
+        values
+    }
 }
 
 impl TryFrom<FileDescDeserialize> for FileDesc {
@@ -46,10 +114,16 @@ impl TryFrom<FileDescDeserialize> for FileDesc {
         let name = value.name.unwrap_or_else(|| String::from(file_stem));
 
         Ok(FileDesc {
+            dir: PathBuf::new(),
             name,
             path: value.path,
             target: value.target,
             template: value.template,
+            units: value.units,
+            profiles: value.profiles,
+            on_change: value.on_change,
+            on_change_hooks: value.on_change_hooks,
+            reload: value.reload,
         })
     }
 }
@@ -58,103 +132,303 @@ impl TryFrom<FileDescDeserialize> for FileDesc {
 pub struct InstallDesc {
     #[serde(skip)]
     pub dir: PathBuf,
+    #[serde(default)]
     pub vars: HashMap<String, String>,
-    #[serde(alias = "file")]
+    #[serde(alias = "file", default)]
     pub files: Vec<FileDesc>,
+    #[serde(rename = "profile", default)]
+    pub profiles: HashMap<String, ProfileDesc>,
+    /// Global hooks declared inline
+    #[serde(default)]
+    pub hooks: InlineHooks,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ProfileDesc {
+    /// Vars overriding the global ones when the profile is active
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+}
+
+/// Files written by an install
+#[derive(Debug, Default)]
+pub struct InstallReport {
+    /// Every target of the install, whether it changed or not
+    pub installed: Vec<PathBuf>,
+    /// Targets whose contents differ from what was there before
+    pub changed: Vec<PathBuf>,
+}
+
+/// Settings that affect a single install
+pub struct InstallOptions<'a> {
+    pub config: &'a Config,
+    /// Where overwritten files are backed up to, depending on the backup policy
+    pub backup_dir: PathBuf,
+    /// Passed to hooks along with the phase and changed files
+    pub hook_env: HookEnv,
+    /// Collects how every hook went, logged once the install is done
+    pub hook_summary: HookSummary,
+    /// Runs hooks and the built-in reload actions
+    pub runner: &'a dyn CommandRunner,
+    /// Where templates are read from and targets written to
+    pub fs: &'a dyn Fs,
+    /// Skips backups, for installs that write to a `DryRunFs` and record commands instead of
+    /// running them
+    pub dry_run: bool,
+}
+
+impl InstallOptions<'_> {
+    fn hook_context(&self, phase: &str, changed: &[PathBuf]) -> HookContext<'_> {
+        HookContext {
+            env: self.hook_env.for_phase(phase, changed),
+            summary: &self.hook_summary,
+            runner: self.runner,
+        }
+    }
 }
 
 impl InstallDesc {
+    /// Layers another install description on top of this one.
+    ///
+    /// Vars and files with the same name are overridden, new ones are added.
+    pub fn extend(&mut self, other: InstallDesc) {
+        self.dir = other.dir;
+        self.vars.extend(other.vars);
+        for (name, profile) in other.profiles {
+            self.profiles
+                .entry(name)
+                .or_default()
+                .vars
+                .extend(profile.vars);
+        }
+
+        self.hooks.extend(other.hooks);
+
+        for file in other.files {
+            match self.files.iter_mut().find(|f| f.name == file.name) {
+                Some(existing) => {
+                    trace!("Overriding file '{}' from {:?}", file.name, file.dir);
+                    *existing = file;
+                }
+                None => self.files.push(file),
+            }
+        }
+    }
+
+    /// Vars with the overrides of the profile applied
+    pub fn profile_vars(&self, profile: Option<&str>) -> Result<HashMap<String, String>, Error> {
+        let mut vars = self.vars.clone();
+        if let Some(profile) = profile {
+            let profile_desc = self
+                .profiles
+                .get(profile)
+                .ok_or_else(|| Error::NoProfile(String::from(profile)))?;
+            vars.extend(profile_desc.vars.clone());
+        }
+
+        Ok(vars)
+    }
+
     pub fn install(
         &self,
         theme_chain: &[&ThemeDesc],
         global_hooks: HookLauncher,
-    ) -> Result<(), Error> {
+        options: &InstallOptions,
+    ) -> Result<InstallReport, Error> {
+        self.install_files(theme_chain, global_hooks, options, |_| true)
+    }
+
+    /// Installs only the files matching `filter`, running hooks as for a full install.
+    ///
+    /// Targets that already have the right contents are left alone. Postinstall hooks run even if
+    /// nothing changed, with an empty `TM_CHANGED_FILES`.
+    pub fn install_files(
+        &self,
+        theme_chain: &[&ThemeDesc],
+        global_hooks: HookLauncher,
+        options: &InstallOptions,
+        filter: impl Fn(&FileDesc) -> bool,
+    ) -> Result<InstallReport, Error> {
         assert!(!theme_chain.is_empty());
         trace!("Installing theme '{}'", theme_chain.last().unwrap().name);
-        for inherited in theme_chain.into_iter().rev().skip(1) {
+        for inherited in theme_chain.iter().rev().skip(1) {
             trace!("Inherits '{}'", inherited.name);
         }
 
+        let profile = options.config.profile.as_deref();
+        let vars = self.profile_vars(profile)?;
+
+        let context = options.hook_context("preinstall", &[]);
         global_hooks
-            .run_preinstall()
+            .run_preinstall(&context)
             .context("Global preinstall hooks")?;
         for theme in theme_chain {
             theme
-                .get_hook_launcher()
-                .run_preinstall()
+                .get_hook_launcher(&options.config.hooks)
+                .run_preinstall(&context)
                 .with_context(|| format!("Theme '{}' preinstall hook", theme.name))?;
         }
 
-        for file in &self.files {
+        let mut report = InstallReport::default();
+        let mut actions = Vec::new();
+        let mut reloads = Vec::new();
+        for file in self
+            .files
+            .iter()
+            .filter(|file| file.in_profile(profile) && filter(file))
+        {
             let res = if file.template {
-                self.install_template(theme_chain, file)
+                self.install_template(theme_chain, file, &vars, options)
             } else {
-                self.install_copy(theme_chain, file)
+                self.install_copy(theme_chain, file, &vars, options)
             };
 
-            res.with_context(|| format!("Installing {}", file.name))?;
+            let (target, changed) = res.with_context(|| format!("Installing {}", file.name))?;
+            if changed {
+                trace!("Changed {:?}", target);
+                report.changed.push(target.clone());
+                for action in on_change_actions(file, theme_chain, &global_hooks)? {
+                    if !actions.contains(&action) {
+                        actions.push(action);
+                    }
+                }
+                for &action in &file.reload {
+                    let task = ReloadTask::new(action, Some(&target));
+                    if !reloads.contains(&task) {
+                        reloads.push(task);
+                    }
+                }
+            }
+            report.installed.push(target);
         }
 
+        let leaf = theme_chain.last().unwrap();
+        hooks::run_on_change(
+            &actions,
+            &leaf.dir,
+            &leaf.name,
+            &options.config.hooks,
+            &options.hook_context("onchange", &report.changed),
+        )
+        .context("On change actions")?;
+
+        if report.changed.is_empty() {
+            trace!("Nothing changed, skipping reload actions");
+        } else {
+            for theme in theme_chain {
+                for &action in &theme.options.reload {
+                    let task = ReloadTask::new(action, None);
+                    if !reloads.contains(&task) {
+                        reloads.push(task);
+                    }
+                }
+            }
+        }
+        reload::run(
+            &reloads,
+            &ReloadContext::new(theme_chain),
+            options.runner,
+            &options.config.hooks,
+            &options.hook_summary,
+        )
+        .context("Reload actions")?;
+
+        let context = options.hook_context("postinstall", &report.changed);
         global_hooks
-            .run_postinstall()
+            .run_postinstall(&context)
             .context("Global postinstall hooks")?;
         for theme in theme_chain {
             theme
-                .get_hook_launcher()
-                .run_postinstall()
-                .with_context(|| format!("Theme '{}' preinstall hook", theme.name))?;
+                .get_hook_launcher(&options.config.hooks)
+                .run_postinstall(&context)
+                .with_context(|| format!("Theme '{}' postinstall hook", theme.name))?;
         }
 
-        Ok(())
+        Ok(report)
     }
 
-    pub fn install_empty(&self, global_hooks: HookLauncher) -> Result<(), Error> {
-        self.install(&[&Default::default()], global_hooks)
+    pub fn install_empty(
+        &self,
+        global_hooks: HookLauncher,
+        options: &InstallOptions,
+    ) -> Result<InstallReport, Error> {
+        self.install(&[&Default::default()], global_hooks, options)
     }
 
-    fn install_template(&self, theme_chain: &[&ThemeDesc], unit: &FileDesc) -> Result<(), Error> {
+    fn install_template(
+        &self,
+        theme_chain: &[&ThemeDesc],
+        unit: &FileDesc,
+        vars: &HashMap<String, String>,
+        options: &InstallOptions,
+    ) -> Result<(PathBuf, bool), Error> {
         trace!("Installing template '{}'", unit.name);
 
-        let path = self.resolve_theme_chain_path(theme_chain, &unit.path);
+        let path = self.resolve_theme_chain_path(options.fs, theme_chain, unit);
 
-        let template =
-            std::fs::read_to_string(self.dir.join(path)).context("Failed to read template file")?;
+        let template = options
+            .fs
+            .read_to_string(&path)
+            .context("Failed to read template file")?;
         let template =
             mustache::compile_str(&template).context("Failed to compile mustache template")?;
 
-        let mut values = HashMap::new();
-        for theme in theme_chain {
-            if let Some(theme_unit) = theme.units.get(&unit.name) {
-                values.extend(&theme_unit.values);
-            }
-        }
-
+        let values = unit.values(theme_chain);
         let result = template.render_to_string(&values).unwrap();
         let target = self
-            .resolve_target(&unit.target)
+            .resolve_target(&unit.target, vars)
             .context("Failed to resolve installation path")?;
+        if is_unchanged(options.fs, &target, result.as_bytes()) {
+            return Ok((target, false));
+        }
+
+        backup(&target, options).context("Failed to back up file")?;
         if let Some(parent) = target.parent() {
-            std::fs::create_dir_all(parent).context("Failed to create parent directory")?;
+            options
+                .fs
+                .create_dir_all(parent)
+                .context("Failed to create parent directory")?;
         }
-        std::fs::write(&target, &result).context("Failed to write file")?;
+        options
+            .fs
+            .write(&target, result.as_bytes())
+            .context("Failed to write file")?;
 
-        Ok(())
+        Ok((target, true))
     }
 
-    fn install_copy(&self, theme_chain: &[&ThemeDesc], unit: &FileDesc) -> Result<(), Error> {
+    fn install_copy(
+        &self,
+        theme_chain: &[&ThemeDesc],
+        unit: &FileDesc,
+        vars: &HashMap<String, String>,
+        options: &InstallOptions,
+    ) -> Result<(PathBuf, bool), Error> {
         trace!("Installing file '{}'", unit.name);
 
-        let path = self.resolve_theme_chain_path(theme_chain, &unit.path);
+        let path = self.resolve_theme_chain_path(options.fs, theme_chain, unit);
 
         let target = self
-            .resolve_target(&unit.target)
+            .resolve_target(&unit.target, vars)
             .context("Failed to resolve installation path")?;
+        let contents = options.fs.read(&path).context("Failed to read file")?;
+        if is_unchanged(options.fs, &target, &contents) {
+            return Ok((target, false));
+        }
+
+        backup(&target, options).context("Failed to back up file")?;
         if let Some(parent) = target.parent() {
-            std::fs::create_dir_all(parent).unwrap();
+            options
+                .fs
+                .create_dir_all(parent)
+                .context("Failed to create parent directory")?;
         }
-        std::fs::copy(path, target).unwrap();
+        options
+            .fs
+            .copy(&path, &target)
+            .context("Failed to copy file")?;
 
-        Ok(())
+        Ok((target, true))
     }
 
     //fn resolve_theme_path(&self, theme: &ThemeDesc, path: &Path) -> Option<PathBuf> {
@@ -170,33 +444,127 @@ impl InstallDesc {
     //self.resolve_theme_path(theme, path).unwrap_or_else(|| self.dir.join(path))
     //}
 
-    fn resolve_theme_chain_path(&self, theme_chain: &[&ThemeDesc], path: &Path) -> PathBuf {
+    pub fn resolve_theme_chain_path(
+        &self,
+        fs: &dyn Fs,
+        theme_chain: &[&ThemeDesc],
+        file: &FileDesc,
+    ) -> PathBuf {
         theme_chain
-            .into_iter()
+            .iter()
             .rev()
-            .map(|theme| theme.dir.join(path))
-            .find(|path| path.exists())
-            .unwrap_or_else(|| self.dir.join(path))
+            .flat_map(|theme| theme.dirs().rev())
+            .map(|dir| dir.join(&file.path))
+            .find(|path| fs.exists(path))
+            .unwrap_or_else(|| file.dir.join(&file.path))
     }
 
-    fn resolve_target(&self, target: &str) -> Result<PathBuf, Error> {
+    fn resolve_target(
+        &self,
+        target: &str,
+        vars: &HashMap<String, String>,
+    ) -> Result<PathBuf, Error> {
         let target = mustache::compile_str(target)
             .unwrap()
-            .render_to_string(&self.vars)
+            .render_to_string(vars)
             .unwrap();
 
         Ok(PathBuf::from(target))
     }
 }
 
-pub fn read_from(dir: &Path) -> Result<InstallDesc, Error> {
+/// Commands and hooks to run when the file changed. Hooks are looked up in the theme chain first,
+/// then in the global hooks.
+fn on_change_actions<'a>(
+    file: &'a FileDesc,
+    theme_chain: &[&'a ThemeDesc],
+    global_hooks: &HookLauncher<'a>,
+) -> Result<Vec<OnChange<'a>>, Error> {
+    let mut actions = file
+        .on_change
+        .iter()
+        .map(|command| OnChange::Command(command))
+        .collect::<Vec<_>>();
+
+    for name in &file.on_change_hooks {
+        let action = theme_chain
+            .iter()
+            .rev()
+            .find_map(|theme| theme.hooks.onchange.find(name))
+            .or_else(|| global_hooks.find_on_change(name))
+            .ok_or_else(|| Error::NoHook(name.clone()))?;
+        actions.push(OnChange::Hook { name, action });
+    }
+
+    Ok(actions)
+}
+
+/// Whether the target already exists with exactly these contents
+fn is_unchanged(fs: &dyn Fs, target: &Path, contents: &[u8]) -> bool {
+    match fs.read(target) {
+        Ok(existing) => existing == contents,
+        Err(_) => false,
+    }
+}
+
+/// Copies the current contents of the target into the backup directory before it is overwritten
+fn backup(target: &Path, options: &InstallOptions) -> Result<(), Error> {
+    if options.dry_run || options.config.backup == BackupPolicy::None || !options.fs.is_file(target)
+    {
+        return Ok(());
+    }
+
+    let relative = target.strip_prefix("/").unwrap_or(target);
+    let backup = options.backup_dir.join(relative);
+    if options.config.backup == BackupPolicy::Original && options.fs.exists(&backup) {
+        return Ok(());
+    }
+
+    trace!("Backing up {:?} to {:?}", target, backup);
+    if let Some(parent) = backup.parent() {
+        options.fs.create_dir_all(parent)?;
+    }
+    options.fs.copy(target, &backup)?;
+
+    Ok(())
+}
+
+pub fn read_from(fs: &dyn Fs, dir: &Path) -> Result<InstallDesc, Error> {
     trace!("Reading install data from {:?}", dir);
 
-    let s =
-        std::fs::read_to_string(dir.join("install.toml")).context("Could not read install.toml")?;
+    let s = fs
+        .read_to_string(&dir.join("install.toml"))
+        .context("Could not read install.toml")?;
     let mut desc: InstallDesc = toml::de::from_str(&s).context("install.toml parse error")?;
 
     desc.dir = dir.to_owned();
+    desc.hooks.set_dir(dir);
+    for file in &mut desc.files {
+        file.dir = dir.to_owned();
+    }
 
     Ok(desc)
 }
+
+/// Reads `install/install.toml` from every layer that has one
+pub fn read_layers(fs: &dyn Fs, dirs: &[PathBuf]) -> Result<InstallDesc, Error> {
+    let mut desc: Option<InstallDesc> = None;
+    for dir in dirs {
+        let install_dir = dir.join("install");
+        if !fs.exists(&install_dir.join("install.toml")) {
+            trace!("No install.toml in {:?}", dir);
+            continue;
+        }
+
+        let layer = read_from(fs, &install_dir)?;
+        match desc {
+            Some(ref mut desc) => desc.extend(layer),
+            None => desc = Some(layer),
+        }
+    }
+
+    match desc {
+        Some(desc) => Ok(desc),
+        None => Err(Error::NoInstallFile),
+    }
+}
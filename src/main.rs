@@ -2,29 +2,71 @@ use std::path::PathBuf;
 
 use argh::FromArgs;
 
+pub mod check;
+pub mod command;
+pub mod config;
+pub mod daemon;
 pub mod error;
+pub mod export;
+pub mod fs;
+pub mod history;
 pub mod hooks;
+pub mod import;
+pub mod init;
 pub mod install;
 pub mod manager;
+pub mod package;
+pub mod reload;
+pub mod scaffold;
+pub mod schedule;
 pub mod themes;
 pub mod utils;
+pub mod watch;
 
 mod prelude {
     pub use crate::error::{Error, ErrorExt, ResultExt};
     pub use crate::utils;
 
-    pub use log::{error, trace, warn};
+    pub use log::{error, info, trace, warn};
 }
 
+use config::{BackupPolicy, ConfigFile, FailurePolicy, HookConfigFile};
+use daemon::Request;
+use export::ExportFormat;
+use fs::RealFs;
 use manager::ThemeManager;
 use prelude::*;
 
 #[derive(FromArgs)]
-///
+/// Manage system-wide themes and config files
 struct Args {
     #[argh(option)]
-    /// dir
-    dir: Option<PathBuf>,
+    /// manager directory, can be given multiple times with later ones taking priority
+    dir: Vec<PathBuf>,
+    #[argh(option)]
+    /// where to keep the installed theme and other state, $XDG_STATE_HOME/theme-manager by default
+    state_dir: Option<PathBuf>,
+    #[argh(option)]
+    /// theme every other theme is layered on top of
+    default_theme: Option<String>,
+    #[argh(option)]
+    /// whether to fail on any problem in the manager directory: true or false
+    strict: Option<bool>,
+    #[argh(option)]
+    /// seconds after which hooks are killed
+    hook_timeout: Option<u64>,
+    #[argh(option)]
+    /// what to do when a hook fails: abort, warn or ignore
+    hook_failure: Option<FailurePolicy>,
+    #[argh(option)]
+    /// which overwritten files to back up: none, original or every
+    backup: Option<BackupPolicy>,
+    #[argh(option)]
+    /// profile to install files and vars for
+    profile: Option<String>,
+    #[argh(switch)]
+    /// do not forward commands to a running daemon
+    no_daemon: bool,
     #[argh(subcommand)]
     command: Subcommand,
 }
@@ -35,26 +77,258 @@ enum Subcommand {
     Install(InstallCommand),
     Display(DisplayCommand),
     Update(UpdateCommand),
+    Current(CurrentCommand),
+    List(ListCommand),
+    GetValue(GetValueCommand),
+    Toggle(ToggleCommand),
+    Cycle(CycleCommand),
+    Auto(AutoCommand),
+    History(HistoryCommand),
+    Rollback(RollbackCommand),
+    Check(CheckCommand),
+    New(NewCommand),
+    Init(InitCommand),
+    Import(ImportCommand),
+    Export(ExportCommand),
+    Pack(PackCommand),
+    Unpack(UnpackCommand),
+    Daemon(DaemonCommand),
+    Watch(WatchCommand),
+    Subscribe(SubscribeCommand),
+    Reload(ReloadCommand),
+}
+
+impl Subcommand {
+    /// The request for commands that a daemon can answer
+    fn request(&self) -> Option<Request> {
+        Some(match self {
+            Subcommand::Install(InstallCommand {
+                theme_name,
+                dry_run: false,
+            }) => Request::Install {
+                theme: theme_name.clone(),
+            },
+            Subcommand::Current(_) => Request::Current,
+            Subcommand::List(_) => Request::List,
+            Subcommand::GetValue(GetValueCommand { unit, value, theme }) => Request::GetValue {
+                theme: theme.clone(),
+                unit: unit.clone(),
+                value: value.clone(),
+            },
+            Subcommand::Toggle(_) => Request::Toggle,
+            _ => return None,
+        })
+    }
 }
 
 #[derive(FromArgs)]
 #[argh(subcommand, name = "install")]
-///
+/// Install a theme
 struct InstallCommand {
     #[argh(positional)]
     theme_name: String,
+    #[argh(switch)]
+    /// print the files that would change without writing them
+    dry_run: bool,
 }
 
 #[derive(FromArgs)]
 #[argh(subcommand, name = "display")]
-///
+/// Display the loaded manager directory
 struct DisplayCommand {}
 
 #[derive(FromArgs)]
 #[argh(subcommand, name = "update")]
-///
+/// Reinstall the currently installed theme
 struct UpdateCommand {}
 
+#[derive(FromArgs)]
+#[argh(subcommand, name = "current")]
+/// Print the installed theme
+struct CurrentCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "list")]
+/// List all themes
+struct ListCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "get-value")]
+/// Print a value of a theme, taking inheritance into account
+struct GetValueCommand {
+    #[argh(positional)]
+    unit: String,
+    #[argh(positional)]
+    value: String,
+    #[argh(option)]
+    /// theme to read from instead of the installed one
+    theme: Option<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "toggle")]
+/// Switch the installed theme to its light or dark counterpart
+struct ToggleCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "cycle")]
+/// Install the next theme in alphabetical order
+struct CycleCommand {
+    #[argh(option)]
+    /// only cycle through themes with this tag
+    tag: Option<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "auto")]
+/// Install the theme the schedule says should be active now
+struct AutoCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "history")]
+/// List previous installs, most recent first
+struct HistoryCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "rollback")]
+/// Restore the files of an earlier install
+struct RollbackCommand {
+    #[argh(positional, default = "1")]
+    /// how many successful installs to go back, 1 by default
+    steps: usize,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "check")]
+/// Check the manager directory for problems
+struct CheckCommand {
+    #[argh(switch)]
+    /// fail on warnings as well as errors
+    deny_warnings: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "new")]
+/// Create a new theme
+struct NewCommand {
+    #[argh(positional)]
+    name: String,
+    #[argh(option)]
+    /// copy units from this theme
+    from: Option<String>,
+    #[argh(option)]
+    /// inherit from this theme
+    inherit: Option<String>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "init")]
+/// Create a manager directory from existing config files
+struct InitCommand {
+    #[argh(positional)]
+    dir: PathBuf,
+    #[argh(option)]
+    /// config file to start managing
+    adopt: Vec<PathBuf>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "import")]
+/// Import a color scheme as a theme
+struct ImportCommand {
+    #[argh(positional)]
+    /// base16, xresources, iterm or alacritty
+    format: String,
+    #[argh(positional)]
+    file: PathBuf,
+    #[argh(option)]
+    /// name of the theme, derived from the file name by default
+    name: Option<String>,
+    #[argh(option, default = "String::from(\"palette\")")]
+    /// unit to put the colors into
+    unit: String,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "export")]
+/// Export the resolved values of a theme
+struct ExportCommand {
+    #[argh(positional)]
+    theme_name: String,
+    #[argh(option)]
+    /// base16, xresources, json or shell
+    format: ExportFormat,
+    #[argh(option, default = "String::from(\"palette\")")]
+    /// unit to take colors from
+    unit: String,
+    #[argh(option, short = 'o')]
+    /// file to write to instead of stdout
+    output: Option<PathBuf>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "pack")]
+/// Pack a theme into an archive
+struct PackCommand {
+    #[argh(positional)]
+    theme_name: String,
+    #[argh(switch)]
+    /// also pack every theme it inherits
+    chain: bool,
+    #[argh(option, short = 'o')]
+    /// archive to create, <theme>.tar by default
+    output: Option<PathBuf>,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "unpack")]
+/// Install themes from an archive
+struct UnpackCommand {
+    #[argh(positional)]
+    archive: PathBuf,
+    #[argh(switch)]
+    /// skip themes that already exist instead of failing
+    skip_existing: bool,
+}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "daemon")]
+/// Keep the manager directory loaded and answer commands over a socket
+struct DaemonCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "watch")]
+/// Re-install files of the installed theme when their sources change
+struct WatchCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "subscribe")]
+/// Print the name of every theme the daemon installs
+struct SubscribeCommand {}
+
+#[derive(FromArgs)]
+#[argh(subcommand, name = "reload")]
+/// Make the daemon read the manager directory again
+struct ReloadCommand {}
+
+fn print_reply(request: &Request, value: &serde_json::Value) {
+    match request {
+        Request::Install { .. } => {}
+        Request::Toggle => println!("Installed '{}'", value.as_str().unwrap_or_default()),
+        Request::Current => match value.as_str() {
+            Some(theme_name) => println!("{}", theme_name),
+            None => eprintln!("No theme installed"),
+        },
+        Request::List => {
+            for name in value.as_array().into_iter().flatten() {
+                println!("{}", name.as_str().unwrap_or_default());
+            }
+        }
+        Request::GetValue { .. } => println!("{}", value.as_str().unwrap_or_default()),
+        Request::Subscribe | Request::Reload | Request::Dirs => {}
+    }
+}
+
 fn main() -> Result<(), String> {
     run().map_err(|e| e.to_string())
 }
@@ -64,28 +338,265 @@ fn run() -> Result<(), Error> {
 
     let args: Args = argh::from_env();
 
-    let dir = match args.dir {
-        Some(dir) => dir,
-        None => std::env::var_os("THEME_MANAGER_DIR")
-            .map(PathBuf::from)
-            .ok_or(Error::NoDir)?,
+    if let Subcommand::Init(InitCommand { dir, adopt }) = args.command {
+        init::init_dir(&RealFs, &dir, &adopt)?;
+        println!("Initialized {}", dir.display());
+        return Ok(());
+    }
+
+    let mut dirs = args.dir;
+    if dirs.is_empty() {
+        if let Some(var) = std::env::var_os("THEME_MANAGER_DIR") {
+            dirs.extend(std::env::split_paths(&var).filter(|dir| !dir.as_os_str().is_empty()));
+        }
+    }
+    let dirs_given = !dirs.is_empty();
+    if dirs.is_empty() {
+        dirs.push(utils::xdg::config_dir().ok_or(Error::NoDir)?);
+    }
+
+    if let Subcommand::Check(CheckCommand { deny_warnings }) = args.command {
+        let report = check::check_dirs(&RealFs, &dirs);
+        for problem in &report.problems {
+            println!("{}", problem);
+        }
+
+        let errors = report.num_errors();
+        let warnings = report.num_warnings();
+        if errors > 0 || (deny_warnings && warnings > 0) {
+            return Err(Error::CheckFailed { errors, warnings });
+        }
+
+        println!("{} errors, {} warnings", errors, warnings);
+        return Ok(());
+    }
+
+    let state_dir = match args.state_dir {
+        Some(state_dir) => state_dir,
+        None => utils::xdg::state_dir().ok_or(Error::NoStateDir)?,
+    };
+    let overrides = ConfigFile {
+        default_theme: args.default_theme,
+        strict: args.strict,
+        profile: args.profile,
+        backup: args.backup,
+        hooks: HookConfigFile {
+            timeout: args.hook_timeout,
+            on_failure: args.hook_failure,
+            ..Default::default()
+        },
+        ..Default::default()
     };
 
-    let manager = ThemeManager::read_from_dir(&dir)?;
+    // A daemon keeps the directories and settings it was started with, so commands that give
+    // their own settings are not forwarded, and neither are ones for other directories
+    let customized = overrides.default_theme.is_some()
+        || overrides.strict.is_some()
+        || overrides.profile.is_some()
+        || overrides.backup.is_some()
+        || overrides.hooks.timeout.is_some()
+        || overrides.hooks.on_failure.is_some();
 
-    match args.command {
-        Subcommand::Install(InstallCommand { theme_name }) => {
-            manager.install_theme(&theme_name);
-            manager.write_installed_theme(&theme_name);
+    let request = args.command.request();
+    let client = if args.no_daemon {
+        None
+    } else {
+        daemon::Client::connect(&state_dir)
+    };
+    match (&args.command, client) {
+        (Subcommand::Daemon(_), _) => return daemon::run(&dirs, &state_dir, overrides),
+        (Subcommand::Watch(_), _) => return watch::run(&dirs, &state_dir, overrides),
+        (Subcommand::Subscribe(_), Some(client)) => {
+            return client.subscribe(|event| match event {
+                daemon::Event::Installed { theme } => println!("{}", theme),
+            })
+        }
+        (Subcommand::Reload(_), Some(mut client)) => {
+            client.request(&Request::Reload)?;
+            return Ok(());
         }
+        (Subcommand::Subscribe(_) | Subcommand::Reload(_), None) => return Err(Error::NoDaemon),
+        (_, Some(mut client)) if !customized => {
+            if let Some(ref request) = request {
+                if dirs_given && !client.serves(&dirs) {
+                    trace!("The daemon serves other directories, not forwarding");
+                } else {
+                    trace!("Forwarding {:?} to the daemon", request);
+                    let value = client.request(request)?;
+                    print_reply(request, &value);
+                    return Ok(());
+                }
+            }
+        }
+        _ => {}
+    }
 
+    let manager = ThemeManager::read_from_dirs(&dirs, &state_dir, overrides)?;
+
+    if let Some(ref request) = request {
+        let value = daemon::execute(&manager, request)?;
+        print_reply(request, &value);
+        return Ok(());
+    }
+
+    match args.command {
         Subcommand::Display(DisplayCommand {}) => {
             dbg!(manager);
         }
 
-        Subcommand::Update(_) => {
-            manager.update();
+        Subcommand::Install(InstallCommand {
+            theme_name,
+            dry_run: true,
+        }) => {
+            let dry_run = manager.dry_run(&theme_name)?;
+            for (path, contents) in &dry_run.written {
+                println!("Would write {} ({} bytes)", path.display(), contents.len());
+            }
+            for argv in &dry_run.commands {
+                println!("Would run {}", argv.join(" "));
+            }
+        }
+
+        Subcommand::Update(_) => match manager.update()? {
+            Some(report) => {
+                for target in &report.changed {
+                    println!("Changed {}", target.display());
+                }
+            }
+            None => eprintln!("No theme installed"),
+        },
+
+        Subcommand::Cycle(CycleCommand { tag }) => {
+            let theme_name = manager.cycle(tag.as_deref())?;
+            println!("Installed '{}'", theme_name);
+        }
+
+        Subcommand::Auto(_) => match manager.auto()? {
+            Some(theme_name) => println!("Installed '{}'", theme_name),
+            None => println!("Scheduled theme is already installed"),
+        },
+
+        Subcommand::History(_) => {
+            let history = history::read(manager.fs(), manager.state_dir())?;
+            let mut steps = 0;
+            for entry in history.entries.iter().rev() {
+                let step = if entry.success {
+                    steps += 1;
+                    (steps - 1).to_string()
+                } else {
+                    String::from("-")
+                };
+                let profile = entry
+                    .profile
+                    .as_ref()
+                    .map(|profile| format!(" ({})", profile))
+                    .unwrap_or_default();
+                let status = if entry.success { "" } else { "  failed" };
+                println!(
+                    "{:>3}  {}  {}{}{}",
+                    step,
+                    entry.time(),
+                    entry.theme,
+                    profile,
+                    status
+                );
+            }
+        }
+
+        Subcommand::Rollback(RollbackCommand { steps }) => {
+            let entry = manager.rollback(steps)?;
+            println!("Rolled back to '{}' from {}", entry.theme, entry.time());
+        }
+
+        Subcommand::New(NewCommand {
+            name,
+            from,
+            inherit,
+        }) => {
+            let theme_dir = manager.create_theme(&name, from.as_deref(), inherit.as_deref())?;
+            println!("Created theme '{}' in {}", name, theme_dir.display());
+        }
+
+        Subcommand::Import(ImportCommand {
+            format,
+            file,
+            name,
+            unit,
+        }) => {
+            let importer = import::find_importer(&format).ok_or(Error::UnknownFormat(format))?;
+            let theme_dir = import::import(
+                manager.fs(),
+                manager.dir(),
+                importer,
+                &file,
+                name.as_deref(),
+                &unit,
+            )?;
+            println!("Imported theme into {}", theme_dir.display());
         }
+
+        Subcommand::Export(ExportCommand {
+            theme_name,
+            format,
+            unit,
+            output,
+        }) => {
+            let chain = manager
+                .theme_chain(&theme_name)?
+                .iter()
+                .map(|theme| theme.name.clone())
+                .collect::<Vec<_>>();
+            let units = manager.resolve_units(&theme_name)?;
+            let exported = export::export(format, &theme_name, &chain, &units, &unit)?;
+
+            match output {
+                Some(output) => manager
+                    .fs()
+                    .write(&output, exported.as_bytes())
+                    .with_context(|| format!("Could not write {:?}", output))?,
+                None => print!("{}", exported),
+            }
+        }
+
+        Subcommand::Pack(PackCommand {
+            theme_name,
+            chain,
+            output,
+        }) => {
+            let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.tar", theme_name)));
+            package::pack(manager.fs(), manager.themes(), &theme_name, chain, &output)?;
+            println!("Packed '{}' into {}", theme_name, output.display());
+        }
+
+        Subcommand::Unpack(UnpackCommand {
+            archive,
+            skip_existing,
+        }) => {
+            let names = package::unpack(
+                manager.fs(),
+                manager.dir(),
+                manager.themes(),
+                &archive,
+                skip_existing,
+            )?;
+            if names.is_empty() {
+                println!("All themes already exist");
+            } else {
+                println!("Unpacked {}", names.join(", "));
+            }
+        }
+
+        Subcommand::Install(_)
+        | Subcommand::Current(_)
+        | Subcommand::List(_)
+        | Subcommand::GetValue(_)
+        | Subcommand::Toggle(_)
+        | Subcommand::Daemon(_)
+        | Subcommand::Watch(_)
+        | Subcommand::Subscribe(_)
+        | Subcommand::Reload(_)
+        | Subcommand::Check(_)
+        | Subcommand::Init(_) => unreachable!(),
     }
 
     Ok(())
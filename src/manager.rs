@@ -1,87 +1,689 @@
 use std::{
     collections::HashMap,
     path::{Path, PathBuf},
+    sync::Arc,
 };
 
-use crate::hooks::{self, HookSet};
-use crate::install::{self, InstallDesc};
+use crate::check::{self, Report};
+use crate::command::{CommandRunner, RecordingRunner, SystemRunner};
+use crate::config::{self, BackupPolicy, Config, ConfigFile};
+use crate::export::{self, ResolvedUnits};
+use crate::fs::{DryRunFs, Fs, RealFs};
+use crate::history;
+use crate::hooks::{self, HookContext, HookEnv, HookSet, HookSummary};
+use crate::install::{self, FileDesc, InstallDesc, InstallOptions, InstallReport};
 use crate::prelude::*;
-use crate::themes::{self, ThemeDesc};
+use crate::scaffold;
+use crate::schedule;
+use crate::themes::{self, ThemeDesc, ThemeOptions};
+
+/// What an install would do
+#[derive(Debug)]
+pub struct DryRun {
+    pub report: InstallReport,
+    /// Files that would be written, with their contents
+    pub written: Vec<(PathBuf, Vec<u8>)>,
+    /// Hooks, on change commands and reload actions that would run, as program and arguments
+    pub commands: Vec<Vec<String>>,
+}
 
 #[derive(Debug)]
 pub struct ThemeManager {
-    dir: PathBuf,
+    /// Manager directories in order of increasing priority
+    dirs: Vec<PathBuf>,
+    state_dir: PathBuf,
+    config: Config,
     install: InstallDesc,
     themes: HashMap<String, ThemeDesc>,
     global_hooks: HookSet,
+    fs: Arc<dyn Fs>,
+    runner: Arc<dyn CommandRunner>,
 }
 
 impl ThemeManager {
-    pub fn read_from_dir(dir: &Path) -> Result<Self, Error> {
-        Ok(ThemeManager {
-            dir: dir.to_owned(),
-            install: install::read_from(&dir.join("install"))
-                .context("Could not read install directory")?,
-            themes: themes::read_from(dir),
-            global_hooks: hooks::read_from(dir),
-        })
+    /// Reads layered manager directories, later ones overriding or extending earlier ones.
+    ///
+    /// Settings in `overrides` take priority over the ones in `config.toml`.
+    pub fn read_from_dirs(
+        dirs: &[PathBuf],
+        state_dir: &Path,
+        overrides: ConfigFile,
+    ) -> Result<Self, Error> {
+        Self::read_with_fs(Arc::new(RealFs), dirs, state_dir, overrides)
     }
 
-    pub fn install_theme(&self, theme: &str) {
-        let mut theme_chain = Vec::new();
-        let mut theme = self.themes.get(theme).expect("Theme does not exist");
-        while let Some(ref inherits) = theme.options.inherits {
-            let next = self
-                .themes
-                .get(inherits)
-                .expect("Inherited theme does not exist");
-            theme_chain.push(theme);
-            theme = next;
+    /// Like `read_from_dirs`, but reads and installs everything through `fs`
+    pub fn read_with_fs(
+        fs: Arc<dyn Fs>,
+        dirs: &[PathBuf],
+        state_dir: &Path,
+        overrides: ConfigFile,
+    ) -> Result<Self, Error> {
+        assert!(!dirs.is_empty());
+        let mut report = Report::default();
+
+        let mut config = config::read_layers(&*fs, dirs).context("Could not read config")?;
+        config.apply_overrides(overrides);
+
+        let install =
+            install::read_layers(&*fs, dirs).context("Could not read install directory")?;
+        let mut global_hooks = hooks::read_layers(&*fs, dirs, &mut report);
+        global_hooks.add_inline(&install.hooks);
+
+        let manager = ThemeManager {
+            dirs: dirs.to_owned(),
+            state_dir: state_dir.to_owned(),
+            config: config.into(),
+            install,
+            themes: themes::read_layers(&*fs, dirs, &mut report),
+            global_hooks,
+            fs,
+            runner: Arc::new(SystemRunner),
+        };
+
+        check::check_usage(&*manager.fs, &manager.install, &manager.themes, &mut report);
+        report.log();
+        if manager.config.strict && report.num_errors() > 0 {
+            return Err(Error::Strict(report.num_errors()));
         }
-        theme_chain.push(theme);
-        if let Some(default) = self.themes.get("default") {
-            if default as *const _ != (*theme_chain.last().unwrap()) as *const _ {
-                theme_chain.push(default);
+
+        Ok(manager)
+    }
+
+    /// Runs hooks and reload actions with `runner` instead of running them for real
+    pub fn with_runner(self, runner: Arc<dyn CommandRunner>) -> Self {
+        ThemeManager { runner, ..self }
+    }
+
+    /// Manager directories in order of increasing priority
+    pub fn dirs(&self) -> &[PathBuf] {
+        &self.dirs
+    }
+
+    /// The directory with the highest priority, where new themes are created
+    pub fn dir(&self) -> &Path {
+        self.dirs.last().unwrap()
+    }
+
+    pub fn themes(&self) -> &HashMap<String, ThemeDesc> {
+        &self.themes
+    }
+
+    pub fn state_dir(&self) -> &Path {
+        &self.state_dir
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// The file system everything is read from and installed to
+    pub fn fs(&self) -> &dyn Fs {
+        &*self.fs
+    }
+
+    fn default_theme(&self) -> Option<&ThemeDesc> {
+        self.themes.get(&self.config.default_theme)
+    }
+
+    fn install_options(
+        &self,
+        theme_chain: &[&ThemeDesc],
+        dry_run: bool,
+    ) -> Result<InstallOptions<'_>, Error> {
+        let backups = self.state_dir.join("backups");
+        let backup_dir = match self.config.backup {
+            BackupPolicy::Every => {
+                let now = std::time::SystemTime::now()
+                    .duration_since(std::time::UNIX_EPOCH)
+                    .unwrap_or_default();
+                backups.join(now.as_secs().to_string())
             }
+            _ => backups.join("original"),
+        };
+
+        Ok(InstallOptions {
+            config: &self.config,
+            backup_dir,
+            hook_env: self.hook_env(theme_chain, dry_run)?,
+            hook_summary: HookSummary::default(),
+            runner: &*self.runner,
+            fs: &*self.fs,
+            dry_run,
+        })
+    }
+
+    /// Describes the install to hooks, including the resolved values both as variables and as a
+    /// JSON file. The file is not written on a dry run.
+    fn hook_env(&self, theme_chain: &[&ThemeDesc], dry_run: bool) -> Result<HookEnv, Error> {
+        let mut env = HookEnv::default();
+        let leaf = theme_chain.last().unwrap();
+        env.set("TM_THEME", leaf.name.as_str());
+        env.set("TM_THEME_DIR", leaf.dir.to_string_lossy());
+        env.set(
+            "TM_THEME_CHAIN",
+            theme_chain
+                .iter()
+                .map(|theme| theme.name.as_str())
+                .collect::<Vec<_>>()
+                .join(":"),
+        );
+        env.set(
+            "TM_PREVIOUS_THEME",
+            self.installed_theme()?.unwrap_or_default(),
+        );
+        env.set("TM_MANAGER_DIR", self.dir().to_string_lossy());
+        env.set("TM_DRY_RUN", if dry_run { "1" } else { "0" });
+
+        let units = resolve_chain_units(theme_chain);
+        for (name, value) in export::env_vars(&units)? {
+            env.set(name, value);
+        }
+
+        if dry_run {
+            return Ok(env);
         }
-        theme_chain.reverse();
-
-        self.install
-            .install(
-                &theme_chain,
-                hooks::HookLauncher::HookSet {
-                    theme_dir: &theme.dir,
-                    theme_name: &theme.name,
-                    hooks: &self.global_hooks,
-                },
+
+        let values_file = self.state_dir.join("values.json");
+        self.fs
+            .create_dir_all(&self.state_dir)
+            .context("Could not create state directory")?;
+        self.fs
+            .write(
+                &values_file,
+                serde_json::to_string_pretty(&units)?.as_bytes(),
             )
-            .unwrap();
+            .context("Could not write values file")?;
+        env.set("TM_VALUES_FILE", values_file.to_string_lossy());
+
+        Ok(env)
     }
 
-    pub fn install_empty(&self) {
-        self.install
-            .install_empty(hooks::HookLauncher::HookSet {
-                theme_dir: &self.dir,
+    /// Returns the inheritance chain of a theme, including the default theme
+    pub fn theme_chain(&self, theme: &str) -> Result<Vec<&ThemeDesc>, Error> {
+        let mut theme_chain = themes::resolve_chain(&self.themes, theme)?;
+        if let Some(default) = self.default_theme() {
+            if !std::ptr::eq(default, theme_chain[0]) {
+                theme_chain.insert(0, default);
+            }
+        }
+
+        Ok(theme_chain)
+    }
+
+    /// Merges the values of every unit along the theme chain
+    pub fn resolve_units(&self, theme: &str) -> Result<ResolvedUnits, Error> {
+        Ok(resolve_chain_units(&self.theme_chain(theme)?))
+    }
+
+    pub fn install_theme(&self, theme: &str) -> Result<InstallReport, Error> {
+        let theme_chain = self.theme_chain(theme)?;
+        let leaf = *theme_chain.last().unwrap();
+
+        let options = self.install_options(&theme_chain, false)?;
+        let res = self
+            .install
+            .install(&theme_chain, self.global_hook_launcher(leaf), &options);
+        options.hook_summary.log();
+
+        let recorded = history::record(
+            &*self.fs,
+            &self.state_dir,
+            theme,
+            self.config.profile.as_deref(),
+            res.as_ref().ok().map(|report| report.installed.as_slice()),
+        )
+        .context("Could not record install in history");
+        // The files are installed either way, so a broken history must not fail the install
+        if let Err(e) = recorded {
+            warn!("{}", e);
+        }
+
+        res
+    }
+
+    /// Goes through an install without changing any files or running any commands, returning
+    /// what it would do. Nothing is recorded in the history.
+    pub fn dry_run(&self, theme: &str) -> Result<DryRun, Error> {
+        let theme_chain = self.theme_chain(theme)?;
+        let leaf = *theme_chain.last().unwrap();
+
+        let fs = DryRunFs::new(&*self.fs);
+        let runner = RecordingRunner {
+            probe: Some(self.runner.clone()),
+            ..Default::default()
+        };
+        let mut options = self.install_options(&theme_chain, true)?;
+        options.fs = &fs;
+        options.runner = &runner;
+
+        let report =
+            self.install
+                .install(&theme_chain, self.global_hook_launcher(leaf), &options)?;
+
+        Ok(DryRun {
+            report,
+            written: fs.written(),
+            commands: runner.argv(),
+        })
+    }
+
+    /// Re-renders the files of the theme matching `filter`.
+    ///
+    /// Hooks only run if any file matches. The install is not recorded in the history.
+    pub fn install_files(
+        &self,
+        theme: &str,
+        filter: impl Fn(&FileDesc) -> bool,
+    ) -> Result<InstallReport, Error> {
+        if !self.install.files.iter().any(&filter) {
+            return Ok(InstallReport::default());
+        }
+
+        let theme_chain = self.theme_chain(theme)?;
+        let leaf = *theme_chain.last().unwrap();
+        let options = self.install_options(&theme_chain, false)?;
+        let res = self.install.install_files(
+            &theme_chain,
+            self.global_hook_launcher(leaf),
+            &options,
+            filter,
+        );
+        options.hook_summary.log();
+
+        res
+    }
+
+    /// Reads the themes again, keeping everything else
+    pub fn reload_themes(&mut self) {
+        let mut report = Report::default();
+        self.themes = themes::read_layers(&*self.fs, &self.dirs, &mut report);
+        check::check_usage(&*self.fs, &self.install, &self.themes, &mut report);
+        report.log();
+    }
+
+    fn global_hook_launcher<'a>(&'a self, theme: &'a ThemeDesc) -> hooks::HookLauncher<'a> {
+        hooks::HookLauncher::HookSet {
+            theme_dir: &theme.dir,
+            theme_name: &theme.name,
+            hooks: &self.global_hooks,
+            config: &self.config.hooks,
+        }
+    }
+
+    /// Returns to an earlier install by restoring its files as they were written, then runs the
+    /// postinstall hooks so that programs pick them up
+    pub fn rollback(&self, steps: usize) -> Result<history::Entry, Error> {
+        let history = history::read(&*self.fs, &self.state_dir)?;
+        let entry = history
+            .previous(steps)
+            .ok_or(Error::NoHistory(steps))?
+            .clone();
+
+        trace!("Rolling back to '{}' from {}", entry.theme, entry.time());
+        // Describe the rollback to hooks before the installed theme changes
+        let theme_chain = match self.theme_chain(&entry.theme) {
+            Ok(theme_chain) => {
+                let env = self.hook_env(&theme_chain, false)?;
+                Some((theme_chain, env))
+            }
+            Err(e) => {
+                warn!("Not running theme hooks: {}", e);
+                None
+            }
+        };
+
+        let restored = history::restore(&*self.fs, &self.state_dir, &entry)?;
+        history::append(
+            &*self.fs,
+            &self.state_dir,
+            history::Entry {
+                timestamp: chrono::Local::now().timestamp(),
+                ..entry.clone()
+            },
+        )?;
+        self.write_installed_theme(&entry.theme)?;
+
+        if let Some((theme_chain, env)) = theme_chain {
+            let summary = HookSummary::default();
+            let context = HookContext {
+                env: env.for_phase("postinstall", &restored),
+                summary: &summary,
+                runner: &*self.runner,
+            };
+            let res = self.run_postinstall(&theme_chain, &context);
+            summary.log();
+            res?;
+        }
+
+        Ok(entry)
+    }
+
+    fn run_postinstall(
+        &self,
+        theme_chain: &[&ThemeDesc],
+        context: &HookContext,
+    ) -> Result<(), Error> {
+        self.global_hook_launcher(theme_chain.last().unwrap())
+            .run_postinstall(context)
+            .context("Global postinstall hooks")?;
+        for theme in theme_chain {
+            theme
+                .get_hook_launcher(&self.config.hooks)
+                .run_postinstall(context)
+                .with_context(|| format!("Theme '{}' postinstall hook", theme.name))?;
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new theme, either copying the units of `from` or with stub units for every template
+    pub fn create_theme(
+        &self,
+        name: &str,
+        from: Option<&str>,
+        inherit: Option<&str>,
+    ) -> Result<PathBuf, Error> {
+        let source = match from {
+            Some(from) => Some(
+                self.themes
+                    .get(from)
+                    .ok_or_else(|| Error::NoTheme(String::from(from)))?,
+            ),
+            None => None,
+        };
+
+        let inherits = inherit
+            .map(String::from)
+            .or_else(|| source.and_then(|source| source.options.inherits.clone()));
+        let theme_chain = match inherits {
+            Some(ref inherits) => self.theme_chain(inherits)?,
+            None => self.default_theme().into_iter().collect(),
+        };
+
+        let options = ThemeOptions {
+            inherits,
+            ..Default::default()
+        };
+        let units = match source {
+            Some(source) => scaffold::copied_units(source)?,
+            None => scaffold::stub_units(&*self.fs, &self.install, &theme_chain)?,
+        };
+
+        scaffold::create_theme_dir(&*self.fs, self.dir(), name, &options, &units)
+    }
+
+    pub fn install_empty(&self) -> Result<(), Error> {
+        let options = self.install_options(&[&ThemeDesc::default()], false)?;
+        let res = self.install.install_empty(
+            hooks::HookLauncher::HookSet {
+                theme_dir: self.dir(),
                 theme_name: "empty",
                 hooks: &self.global_hooks,
-            })
-            .unwrap();
+                config: &self.config.hooks,
+            },
+            &options,
+        );
+        options.hook_summary.log();
+
+        res.map(|_| ())
+    }
+
+    pub fn installed_theme(&self) -> Result<Option<String>, Error> {
+        let mut installed_theme_file = self.state_dir.join("installed");
+        if !self.fs.exists(&installed_theme_file) {
+            // Older versions kept the installed theme inside the manager directory
+            let legacy = self.dir().join(".cache/installed");
+            if !self.fs.exists(&legacy) {
+                return Ok(None);
+            }
+            trace!("Reading installed theme from legacy location {:?}", legacy);
+            installed_theme_file = legacy;
+        }
+
+        let theme_name = self
+            .fs
+            .read_to_string(&installed_theme_file)
+            .context("Could not read installed theme file")?;
+        Ok(Some(String::from(theme_name.trim())))
+    }
+
+    /// Reinstalls the installed theme, returning what changed
+    pub fn update(&self) -> Result<Option<InstallReport>, Error> {
+        match self.installed_theme()? {
+            Some(theme_name) => Ok(Some(self.install_theme(&theme_name)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Installs the counterpart of the installed theme, returning its name
+    pub fn toggle(&self) -> Result<String, Error> {
+        let current = self.installed_theme()?.ok_or(Error::NotInstalled)?;
+        let counterpart = themes::counterpart(&self.themes, &current)
+            .ok_or_else(|| Error::NoCounterpart(current.clone()))?;
+
+        self.switch_to(&counterpart.name)?;
+        Ok(counterpart.name.clone())
     }
 
-    pub fn update(&self) {
-        let installed_theme_file = self.dir.join(".cache/installed");
-        if installed_theme_file.exists() {
-            let theme_name = std::fs::read_to_string(installed_theme_file)
-                .expect("Could not read installed theme file");
-            self.install_theme(&theme_name);
+    /// Installs the next theme with the tag, or the next installable theme, returning its name
+    pub fn cycle(&self, tag: Option<&str>) -> Result<String, Error> {
+        let current = self.installed_theme()?;
+        let next = themes::cycle_next(
+            &self.themes,
+            &self.config.default_theme,
+            current.as_deref(),
+            tag,
+        )
+        .ok_or(Error::NoCycle)?;
+
+        self.switch_to(&next.name)?;
+        Ok(next.name.clone())
+    }
+
+    /// Installs the theme scheduled for now if it is not installed already, returning its name
+    pub fn auto(&self) -> Result<Option<String>, Error> {
+        let scheduled = schedule::active_theme(
+            &self.config.schedule,
+            self.config.location,
+            chrono::Local::now(),
+        )?
+        .ok_or(if self.config.schedule.is_empty() {
+            Error::NoSchedule
         } else {
-            eprintln!("No theme installed");
+            Error::NoSunriseOrSunset
+        })?;
+
+        if self.installed_theme()?.as_deref() == Some(scheduled) {
+            trace!("Scheduled theme '{}' is already installed", scheduled);
+            return Ok(None);
         }
+
+        self.switch_to(scheduled)?;
+        Ok(Some(String::from(scheduled)))
+    }
+
+    /// Installs a theme and records it as the installed one
+    pub fn switch_to(&self, theme_name: &str) -> Result<InstallReport, Error> {
+        let report = self.install_theme(theme_name)?;
+        self.write_installed_theme(theme_name)?;
+
+        Ok(report)
+    }
+
+    pub fn write_installed_theme(&self, theme_name: &str) -> Result<(), Error> {
+        self.fs
+            .create_dir_all(&self.state_dir)
+            .context("Could not create state directory")?;
+        self.fs
+            .write(&self.state_dir.join("installed"), theme_name.as_bytes())
+            .context("Could not record installed theme")?;
+
+        Ok(())
+    }
+}
+
+fn resolve_chain_units(theme_chain: &[&ThemeDesc]) -> ResolvedUnits {
+    let mut units = ResolvedUnits::new();
+    for theme in theme_chain {
+        for (unit_name, unit) in &theme.units {
+            units
+                .entry(unit_name.clone())
+                .or_default()
+                .extend(unit.values.clone());
+        }
+    }
+
+    units
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+
+    fn manager(fs: &Arc<MemoryFs>, runner: &Arc<RecordingRunner>) -> ThemeManager {
+        ThemeManager::read_with_fs(
+            fs.clone(),
+            &[PathBuf::from("/m")],
+            Path::new("/state"),
+            ConfigFile::default(),
+        )
+        .unwrap()
+        .with_runner(runner.clone())
     }
 
-    pub fn write_installed_theme(&self, theme_name: &str) {
-        std::fs::create_dir_all(self.dir.join(".cache")).expect("Could not create cache directory");
-        std::fs::write(self.dir.join(".cache/installed"), theme_name)
-            .expect("Could not record installed theme");
+    fn memory_fs() -> Arc<MemoryFs> {
+        Arc::new(MemoryFs::with_files([
+            (
+                "/m/install/install.toml",
+                "[vars]\nout = \"/home/out\"\n\n\
+                 [[files]]\npath = \"colors.conf\"\ntarget = \"{{out}}/colors.conf\"\n\
+                 on_change = [\"pkill -USR1 kitty\"]\n\n\
+                 [[files]]\npath = \"logo.txt\"\ntarget = \"{{out}}/logo.txt\"\ntemplate = false\n",
+            ),
+            ("/m/install/colors.conf", "fg={{fg}} bg={{bg}}"),
+            ("/m/install/logo.txt", "{{plain}}"),
+            ("/m/themes/base/units/colors/fg", "white"),
+            ("/m/themes/base/units/colors/bg", "gray"),
+            ("/m/themes/dark/theme.toml", "inherits = \"base\""),
+            ("/m/themes/dark/units/colors/bg", "black"),
+            ("/m/themes/dark/logo.txt", "dark logo"),
+        ]))
+    }
+
+    #[test]
+    fn install_in_memory() {
+        let fs = memory_fs();
+        let runner = Arc::new(RecordingRunner::default());
+        let manager = manager(&fs, &runner);
+
+        let report = manager.install_files("dark", |_| true).unwrap();
+        let out = |name: &str| {
+            fs.read_to_string(&Path::new("/home/out").join(name))
+                .unwrap()
+        };
+        assert_eq!(out("colors.conf"), "fg=white bg=black");
+        assert_eq!(out("logo.txt"), "dark logo");
+        assert_eq!(report.changed.len(), 2);
+        assert_eq!(runner.argv(), vec![vec!["sh", "-c", "pkill -USR1 kitty"]]);
+
+        let report = manager.install_files("dark", |_| true).unwrap();
+        assert_eq!(report.installed.len(), 2);
+        assert!(report.changed.is_empty());
+
+        let values = fs.read(Path::new("/state/values.json")).unwrap();
+        let dry_run = manager.dry_run("base").unwrap();
+        assert_eq!(
+            dry_run.written,
+            vec![
+                (
+                    PathBuf::from("/home/out/colors.conf"),
+                    b"fg=white bg=gray".to_vec()
+                ),
+                (PathBuf::from("/home/out/logo.txt"), b"{{plain}}".to_vec()),
+            ]
+        );
+        assert_eq!(
+            dry_run.commands,
+            vec![vec!["sh", "-c", "pkill -USR1 kitty"]]
+        );
+        assert_eq!(out("colors.conf"), "fg=white bg=black");
+        assert_eq!(out("logo.txt"), "dark logo");
+        assert_eq!(fs.read(Path::new("/state/values.json")).unwrap(), values);
+        assert_eq!(runner.argv().len(), 1);
+    }
+
+    #[test]
+    fn switch_and_rollback_in_memory() {
+        let fs = memory_fs();
+        let runner = Arc::new(RecordingRunner::default());
+        let manager = manager(&fs, &runner);
+
+        manager.switch_to("base").unwrap();
+        manager.switch_to("dark").unwrap();
+        assert_eq!(manager.installed_theme().unwrap().as_deref(), Some("dark"));
+        let history = history::read(&*fs, Path::new("/state")).unwrap();
+        assert_eq!(history.restorable().count(), 2);
+
+        let entry = manager.rollback(1).unwrap();
+        assert_eq!(entry.theme, "base");
+        assert_eq!(manager.installed_theme().unwrap().as_deref(), Some("base"));
+        assert_eq!(
+            fs.read_to_string(Path::new("/home/out/colors.conf"))
+                .unwrap(),
+            "fg=white bg=gray"
+        );
+        assert_eq!(
+            fs.read_to_string(Path::new("/home/out/logo.txt")).unwrap(),
+            "{{plain}}"
+        );
+    }
+
+    #[test]
+    fn failed_create_leaves_no_theme() {
+        let fs = memory_fs();
+        fs.append(
+            Path::new("/m/install/install.toml"),
+            b"\n[[files]]\npath = \"missing.conf\"\ntarget = \"{{out}}/missing.conf\"\n",
+        )
+        .unwrap();
+        let runner = Arc::new(RecordingRunner::default());
+        let manager = manager(&fs, &runner);
+
+        assert!(manager.create_theme("new", None, None).is_err());
+        assert!(!fs.exists(Path::new("/m/themes/new")));
+
+        let theme_dir = manager.create_theme("new", Some("dark"), None).unwrap();
+        assert_eq!(
+            fs.read_to_string(&theme_dir.join("unit-colors.toml"))
+                .unwrap(),
+            "bg = \"black\"\n"
+        );
+    }
+
+    #[test]
+    fn dry_run_records_reload_actions() {
+        let fs = memory_fs();
+        fs.write(
+            Path::new("/m/themes/dark/theme.toml"),
+            b"inherits = \"base\"\nreload = [\"kitty\"]",
+        )
+        .unwrap();
+        let runner = Arc::new(RecordingRunner {
+            running: vec![String::from("kitty")],
+            ..Default::default()
+        });
+        let manager = manager(&fs, &runner);
+
+        let dry_run = manager.dry_run("dark").unwrap();
+        assert_eq!(
+            dry_run.commands,
+            vec![
+                vec!["sh", "-c", "pkill -USR1 kitty"],
+                vec!["pkill", "-USR1", "-x", "kitty"],
+            ]
+        );
+        assert!(runner.argv().is_empty());
     }
 }
@@ -0,0 +1,369 @@
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::check::Report;
+use crate::fs::Fs;
+use crate::prelude::*;
+use crate::scaffold;
+use crate::themes::{self, ThemeDesc};
+
+/// Describes the contents of a theme archive.
+///
+/// The archive itself is laid out like a manager directory,
+/// with every theme in `themes/<name>` and the manifest at the root.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub theme: String,
+    pub themes: Vec<String>,
+}
+
+const MANIFEST: &str = "manifest.toml";
+
+pub fn pack(
+    fs: &dyn Fs,
+    themes: &HashMap<String, ThemeDesc>,
+    theme: &str,
+    with_chain: bool,
+    output: &Path,
+) -> Result<(), Error> {
+    let packed = if with_chain {
+        themes::resolve_chain(themes, theme)?
+    } else {
+        vec![themes
+            .get(theme)
+            .ok_or_else(|| Error::NoTheme(String::from(theme)))?]
+    };
+
+    let manifest = Manifest {
+        theme: String::from(theme),
+        themes: packed.iter().map(|theme| theme.name.clone()).collect(),
+    };
+    let manifest = toml::to_string(&manifest).context("Could not serialize manifest")?;
+
+    let mut archive = tar::Builder::new(Vec::new());
+    append_file(
+        &mut archive,
+        Path::new(MANIFEST),
+        manifest.as_bytes(),
+        false,
+    )
+    .context("Could not write manifest")?;
+
+    for theme in packed {
+        trace!("Packing theme '{}' from {:?}", theme.name, theme.dir);
+        if !theme.layer_dirs.is_empty() {
+            warn!(
+                "Theme '{}' is also in {:?}, only {:?} is packed",
+                theme.name, theme.layer_dirs, theme.dir
+            );
+        }
+        append_dir(
+            fs,
+            &mut archive,
+            &Path::new("themes").join(&theme.name),
+            &theme.dir,
+        )
+        .with_context(|| format!("Could not pack theme '{}'", theme.name))?;
+    }
+
+    let data = archive.into_inner().context("Could not finish archive")?;
+    fs.write(output, &data)
+        .with_context(|| format!("Could not write {:?}", output))?;
+
+    Ok(())
+}
+
+fn append_file(
+    archive: &mut tar::Builder<Vec<u8>>,
+    path: &Path,
+    data: &[u8],
+    executable: bool,
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(if executable { 0o755 } else { 0o644 });
+    header.set_cksum();
+    archive.append_data(&mut header, path, data)
+}
+
+/// Adds `dir` and everything below it to the archive under `path`
+fn append_dir(
+    fs: &dyn Fs,
+    archive: &mut tar::Builder<Vec<u8>>,
+    path: &Path,
+    dir: &Path,
+) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Directory);
+    header.set_size(0);
+    header.set_mode(0o755);
+    header.set_cksum();
+    archive.append_data(&mut header, path, std::io::empty())?;
+
+    let mut entries = fs.read_dir(dir)?;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    for entry in entries {
+        let file_name = entry.path.file_name().unwrap();
+        if entry.is_dir {
+            append_dir(fs, archive, &path.join(file_name), &entry.path)?;
+        } else {
+            let data = fs.read(&entry.path)?;
+            let executable = fs.is_executable(&entry.path);
+            append_file(archive, &path.join(file_name), &data, executable)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Installs the themes from an archive into the manager directory and returns their names.
+///
+/// The archive is extracted into an ignored `_unpack` directory first and only moved into place
+/// once every theme in the manifest has been read without errors and everything it inherits exists.
+pub fn unpack(
+    fs: &dyn Fs,
+    dir: &Path,
+    themes: &HashMap<String, ThemeDesc>,
+    archive: &Path,
+    skip_existing: bool,
+) -> Result<Vec<String>, Error> {
+    let staging = dir.join(format!("_unpack{}", std::process::id()));
+    let res = unpack_staged(fs, dir, &staging, themes, archive, skip_existing);
+    if fs.exists(&staging) {
+        if let Err(e) = fs.remove_dir_all(&staging) {
+            warn!("Could not remove {:?}: {}", staging, e);
+        }
+    }
+
+    res
+}
+
+fn unpack_staged(
+    fs: &dyn Fs,
+    dir: &Path,
+    staging: &Path,
+    themes: &HashMap<String, ThemeDesc>,
+    archive: &Path,
+    skip_existing: bool,
+) -> Result<Vec<String>, Error> {
+    let data = fs
+        .read(archive)
+        .with_context(|| format!("Could not open {:?}", archive))?;
+    extract(fs, &data, staging).context("Could not extract archive")?;
+
+    let manifest = fs
+        .read_to_string(&staging.join(MANIFEST))
+        .context("Archive does not contain a manifest")?;
+    let manifest: Manifest = toml::de::from_str(&manifest).context("Invalid manifest")?;
+
+    let mut report = Report::default();
+    let unpacked = themes::read_from(fs, staging, &mut report);
+    for problem in &report.problems {
+        error!("{}", problem);
+    }
+    if report.num_errors() > 0 {
+        return Err(Error::InvalidArchive(String::from(
+            "Archive contains invalid themes",
+        )));
+    }
+
+    let mut installed = Vec::new();
+    for name in &manifest.themes {
+        let theme = unpacked.get(name).ok_or_else(|| {
+            Error::InvalidArchive(format!("Theme '{}' is missing from the archive", name))
+        })?;
+
+        if themes.contains_key(name) || fs.exists(&scaffold::theme_dir(fs, dir, name)) {
+            if skip_existing {
+                warn!("Theme '{}' already exists, skipping it", name);
+                continue;
+            }
+            return Err(Error::ThemeExists(name.clone()));
+        }
+
+        installed.push((name.clone(), theme.dir.clone()));
+    }
+    check_inheritance(themes, &unpacked, &installed)?;
+
+    let mut names = Vec::new();
+    for (name, source) in installed {
+        let target: PathBuf = scaffold::theme_dir(fs, dir, &name);
+        if let Some(parent) = target.parent() {
+            fs.create_dir_all(parent)
+                .context("Could not create themes directory")?;
+        }
+        fs.rename(&source, &target)
+            .with_context(|| format!("Could not move theme '{}' into place", name))?;
+        names.push(name);
+    }
+
+    Ok(names)
+}
+
+/// Checks that the inheritance chains of the themes about to be installed are complete,
+/// looking up inherited themes among them first and then among the existing ones
+fn check_inheritance(
+    themes: &HashMap<String, ThemeDesc>,
+    unpacked: &HashMap<String, ThemeDesc>,
+    installed: &[(String, PathBuf)],
+) -> Result<(), Error> {
+    let find = |name: &str| {
+        if installed.iter().any(|(installed, _)| installed == name) {
+            unpacked.get(name)
+        } else {
+            themes.get(name)
+        }
+    };
+
+    for (name, _) in installed {
+        let mut chain = vec![name.as_str()];
+        let mut theme = &unpacked[name];
+        while let Some(inherits) = theme.options.inherits.as_deref() {
+            if chain.contains(&inherits) {
+                chain.push(inherits);
+                return Err(Error::InheritCycle(chain.join(" -> ")));
+            }
+            theme = find(inherits)
+                .ok_or_else(|| Error::NoTheme(String::from(inherits)))
+                .with_context(|| format!("Theme '{}' inherits a missing theme", theme.name))?;
+            chain.push(inherits);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the directories and regular files of a tar archive below `dir`
+fn extract(fs: &dyn Fs, data: &[u8], dir: &Path) -> std::io::Result<()> {
+    fs.create_dir_all(dir)?;
+    for entry in tar::Archive::new(Cursor::new(data)).entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+        if !path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return Err(std::io::Error::other(format!(
+                "Invalid path {:?} in archive",
+                path
+            )));
+        }
+
+        let target = dir.join(&path);
+        match entry.header().entry_type() {
+            tar::EntryType::Directory => fs.create_dir_all(&target)?,
+            tar::EntryType::Regular => {
+                let mut contents = Vec::new();
+                entry.read_to_end(&mut contents)?;
+                if let Some(parent) = target.parent() {
+                    fs.create_dir_all(parent)?;
+                }
+                fs.write(&target, &contents)?;
+                if entry.header().mode()? & 0o111 != 0 {
+                    fs.set_executable(&target)?;
+                }
+            }
+            _ => warn!(
+                "Skipping {:?} in archive, only regular files are extracted",
+                path
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+
+    fn read_themes(fs: &dyn Fs, dir: &str) -> HashMap<String, ThemeDesc> {
+        let mut report = Report::default();
+        let themes = themes::read_from(fs, Path::new(dir), &mut report);
+        assert_eq!(report.num_errors(), 0);
+        themes
+    }
+
+    #[test]
+    fn round_trip() {
+        let fs = MemoryFs::with_files([
+            ("/a/themes/base/unit-colors.toml", "fg = \"white\""),
+            ("/a/themes/dark/theme.toml", "inherits = \"base\""),
+            ("/a/themes/dark/unit-colors.toml", "bg = \"black\""),
+            ("/a/themes/dark/hook-postinstall-reload", "true"),
+            ("/b/themes/light/unit-colors.toml", "bg = \"white\""),
+        ]);
+        fs.set_executable(Path::new("/a/themes/dark/hook-postinstall-reload"))
+            .unwrap();
+        let archive = Path::new("/dark.tar");
+
+        pack(&fs, &read_themes(&fs, "/a"), "dark", true, archive).unwrap();
+        let mut names = unpack(
+            &fs,
+            Path::new("/b"),
+            &read_themes(&fs, "/b"),
+            archive,
+            false,
+        )
+        .unwrap();
+        names.sort();
+        assert_eq!(names, vec!["base", "dark"]);
+
+        for file in [
+            "themes/base/unit-colors.toml",
+            "themes/dark/theme.toml",
+            "themes/dark/unit-colors.toml",
+        ] {
+            assert_eq!(
+                fs.read(&Path::new("/b").join(file)).unwrap(),
+                fs.read(&Path::new("/a").join(file)).unwrap()
+            );
+        }
+        assert!(fs.is_executable(Path::new("/b/themes/dark/hook-postinstall-reload")));
+        assert!(!fs.exists(&Path::new("/b").join(format!("_unpack{}", std::process::id()))));
+    }
+
+    #[test]
+    fn missing_inherited_theme() {
+        let fs = MemoryFs::with_files([
+            ("/a/themes/base/unit-colors.toml", "fg = \"white\""),
+            ("/a/themes/dark/theme.toml", "inherits = \"base\""),
+            ("/b/themes/light/unit-colors.toml", "bg = \"white\""),
+        ]);
+        let archive = Path::new("/dark.tar");
+
+        pack(&fs, &read_themes(&fs, "/a"), "dark", false, archive).unwrap();
+        assert!(unpack(
+            &fs,
+            Path::new("/b"),
+            &read_themes(&fs, "/b"),
+            archive,
+            false
+        )
+        .is_err());
+        assert!(!fs.exists(Path::new("/b/themes/dark")));
+    }
+
+    #[test]
+    fn path_traversal() {
+        let mut archive = tar::Builder::new(Vec::new());
+        let mut header = tar::Header::new_gnu();
+        let name = b"../evil.toml";
+        header.as_old_mut().name[..name.len()].copy_from_slice(name);
+        header.set_size(4);
+        header.set_mode(0o644);
+        header.set_cksum();
+        archive.append(&header, &b"evil"[..]).unwrap();
+        let data = archive.into_inner().unwrap();
+
+        let fs = MemoryFs::default();
+        assert!(extract(&fs, &data, Path::new("/m/_unpack")).is_err());
+        assert!(!fs.exists(Path::new("/m/evil.toml")));
+    }
+}
@@ -0,0 +1,321 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::command::{CommandLine, CommandRunner, RunOptions};
+use crate::config::HookConfig;
+use crate::hooks::{self, HookSummary};
+use crate::prelude::*;
+use crate::themes::{ThemeDesc, Variant};
+
+/// Built-in actions that make programs pick up newly installed files
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reload {
+    /// Merges the file into the X resource database
+    Xrdb,
+    I3,
+    Sway,
+    Kitty,
+    /// Sets the GTK theme to the `theme` value of the `gtk` unit and the color scheme to the
+    /// variant of the theme
+    Gtk,
+}
+
+impl Reload {
+    pub fn name(self) -> &'static str {
+        match self {
+            Reload::Xrdb => "xrdb",
+            Reload::I3 => "i3",
+            Reload::Sway => "sway",
+            Reload::Kitty => "kitty",
+            Reload::Gtk => "gtk",
+        }
+    }
+}
+
+/// A reload action and the file it was requested for
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReloadTask {
+    pub action: Reload,
+    /// Only kept for actions that load a file
+    pub file: Option<PathBuf>,
+}
+
+impl ReloadTask {
+    pub fn new(action: Reload, file: Option<&Path>) -> Self {
+        ReloadTask {
+            action,
+            file: match action {
+                Reload::Xrdb => file.map(Path::to_owned),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// What the actions need to know about the installed theme
+#[derive(Debug, Default)]
+pub struct ReloadContext {
+    pub gtk_theme: Option<String>,
+    pub variant: Option<Variant>,
+    /// X display to load resources into
+    pub display: Option<String>,
+    pub home: Option<PathBuf>,
+}
+
+impl ReloadContext {
+    pub fn new(theme_chain: &[&ThemeDesc]) -> Self {
+        let gtk_theme = theme_chain.iter().rev().find_map(|theme| {
+            theme
+                .units
+                .get("gtk")
+                .and_then(|unit| unit.values.get("theme"))
+        });
+
+        ReloadContext {
+            gtk_theme: gtk_theme.map(|theme| String::from(theme.trim())),
+            variant: theme_chain
+                .iter()
+                .rev()
+                .find_map(|theme| theme.options.variant),
+            display: std::env::var("DISPLAY").ok().filter(|d| !d.is_empty()),
+            home: std::env::var_os("HOME").map(PathBuf::from),
+        }
+    }
+}
+
+/// The commands to run for a task, or why it is skipped
+fn commands(
+    task: &ReloadTask,
+    context: &ReloadContext,
+    runner: &dyn CommandRunner,
+) -> Result<Vec<CommandLine>, String> {
+    let running = |process: &str| {
+        if runner.is_running(process) {
+            Ok(())
+        } else {
+            Err(format!("{} is not running", process))
+        }
+    };
+
+    match task.action {
+        Reload::Xrdb => {
+            if context.display.is_none() {
+                return Err(String::from("no X display"));
+            }
+            if !runner.is_installed("xrdb") {
+                return Err(String::from("xrdb is not installed"));
+            }
+            let file = task
+                .file
+                .clone()
+                .or_else(|| Some(context.home.as_ref()?.join(".Xresources")))
+                .ok_or_else(|| String::from("no file to merge"))?;
+
+            Ok(vec![CommandLine::new("xrdb").arg("-merge").arg(file)])
+        }
+
+        Reload::I3 => {
+            running("i3")?;
+            Ok(vec![CommandLine::new("i3-msg").arg("reload")])
+        }
+
+        Reload::Sway => {
+            running("sway")?;
+            Ok(vec![CommandLine::new("swaymsg").arg("reload")])
+        }
+
+        Reload::Kitty => {
+            running("kitty")?;
+            Ok(vec![
+                CommandLine::new("pkill").args(["-USR1", "-x", "kitty"])
+            ])
+        }
+
+        Reload::Gtk => {
+            if !runner.is_installed("gsettings") {
+                return Err(String::from("gsettings is not installed"));
+            }
+            let gsettings = |key: &str, value: &str| {
+                CommandLine::new("gsettings").args([
+                    "set",
+                    "org.gnome.desktop.interface",
+                    key,
+                    value,
+                ])
+            };
+
+            let mut commands = Vec::new();
+            if let Some(ref theme) = context.gtk_theme {
+                commands.push(gsettings("gtk-theme", theme));
+            }
+            match context.variant {
+                Some(Variant::Light) => commands.push(gsettings("color-scheme", "prefer-light")),
+                Some(Variant::Dark) => commands.push(gsettings("color-scheme", "prefer-dark")),
+                None => {}
+            }
+            if commands.is_empty() {
+                return Err(String::from("neither a gtk theme nor a variant is set"));
+            }
+
+            Ok(commands)
+        }
+    }
+}
+
+/// Runs the actions, skipping the ones for programs that aren't running
+pub fn run(
+    tasks: &[ReloadTask],
+    context: &ReloadContext,
+    runner: &dyn CommandRunner,
+    config: &HookConfig,
+    summary: &HookSummary,
+) -> Result<(), Error> {
+    for task in tasks {
+        let name = task.action.name();
+        let commands = match commands(task, context, runner) {
+            Ok(commands) => commands,
+            Err(reason) => {
+                trace!("Skipping {} reload: {}", name, reason);
+                continue;
+            }
+        };
+
+        let config = config.for_hook(name);
+        let options = RunOptions {
+            timeout: config.timeout,
+            output_prefix: if config.capture {
+                Some(String::from(name))
+            } else {
+                None
+            },
+        };
+        hooks::record("Reload", name, config, summary, || {
+            commands
+                .iter()
+                .try_for_each(|command| runner.run(command, &options))
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::RecordingRunner;
+    use crate::config::FailurePolicy;
+
+    fn context() -> ReloadContext {
+        ReloadContext {
+            gtk_theme: Some(String::from("Adwaita-dark")),
+            variant: Some(Variant::Dark),
+            display: Some(String::from(":0")),
+            home: Some(PathBuf::from("/home/user")),
+        }
+    }
+
+    fn tasks(actions: &[Reload]) -> Vec<ReloadTask> {
+        actions
+            .iter()
+            .map(|&action| ReloadTask::new(action, Some(Path::new("/home/user/.Xresources.d"))))
+            .collect()
+    }
+
+    #[test]
+    fn skips_programs_that_are_not_running() {
+        let runner = RecordingRunner {
+            running: vec![String::from("kitty")],
+            ..Default::default()
+        };
+        run(
+            &tasks(&[Reload::I3, Reload::Sway, Reload::Kitty, Reload::Xrdb]),
+            &context(),
+            &runner,
+            &HookConfig::default(),
+            &HookSummary::default(),
+        )
+        .unwrap();
+
+        assert_eq!(runner.argv(), vec![vec!["pkill", "-USR1", "-x", "kitty"]]);
+    }
+
+    #[test]
+    fn commands_of_actions() {
+        let runner = RecordingRunner {
+            running: vec![String::from("i3")],
+            installed: vec![String::from("xrdb"), String::from("gsettings")],
+            ..Default::default()
+        };
+        run(
+            &tasks(&[Reload::Xrdb, Reload::I3, Reload::Gtk]),
+            &context(),
+            &runner,
+            &HookConfig::default(),
+            &HookSummary::default(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            runner.argv(),
+            vec![
+                vec!["xrdb", "-merge", "/home/user/.Xresources.d"],
+                vec!["i3-msg", "reload"],
+                vec![
+                    "gsettings",
+                    "set",
+                    "org.gnome.desktop.interface",
+                    "gtk-theme",
+                    "Adwaita-dark"
+                ],
+                vec![
+                    "gsettings",
+                    "set",
+                    "org.gnome.desktop.interface",
+                    "color-scheme",
+                    "prefer-dark"
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn failure_policy() {
+        let runner = RecordingRunner {
+            running: vec![String::from("i3"), String::from("sway")],
+            failing: vec![String::from("i3-msg")],
+            ..Default::default()
+        };
+        let summary = HookSummary::default();
+        assert!(run(
+            &tasks(&[Reload::I3, Reload::Sway]),
+            &context(),
+            &runner,
+            &HookConfig::default(),
+            &summary,
+        )
+        .is_err());
+        assert_eq!(runner.argv().len(), 1);
+
+        let runner = RecordingRunner {
+            calls: Default::default(),
+            ..runner
+        };
+        let config = HookConfig {
+            on_failure: FailurePolicy::Warn,
+            ..Default::default()
+        };
+        let summary = HookSummary::default();
+        run(
+            &tasks(&[Reload::I3, Reload::Sway]),
+            &context(),
+            &runner,
+            &config,
+            &summary,
+        )
+        .unwrap();
+        assert_eq!(runner.argv().len(), 2);
+        assert_eq!(summary.num_failed(), 1);
+    }
+}
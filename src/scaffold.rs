@@ -0,0 +1,117 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::fs::Fs;
+use crate::install::InstallDesc;
+use crate::prelude::*;
+use crate::themes::{ThemeDesc, ThemeOptions};
+
+/// Returns the directory a new theme should be created in.
+///
+/// Themes go into `themes/` if the manager directory has one, and into `theme-<name>` otherwise.
+pub fn theme_dir(fs: &dyn Fs, dir: &Path, name: &str) -> PathBuf {
+    let themes_dir = dir.join("themes");
+    if fs.is_dir(&themes_dir) {
+        themes_dir.join(name)
+    } else {
+        dir.join(format!("theme-{}", name))
+    }
+}
+
+/// Creates a theme directory with a `theme.toml` and a compound unit file for each of `units`,
+/// given as unit name and file contents.
+///
+/// Everything that can fail before writing should be done before calling this, if a write fails
+/// the directory is removed again.
+pub fn create_theme_dir(
+    fs: &dyn Fs,
+    dir: &Path,
+    name: &str,
+    options: &ThemeOptions,
+    units: &[(String, String)],
+) -> Result<PathBuf, Error> {
+    if name.is_empty() || name.contains('-') || name.starts_with('_') {
+        return Err(Error::InvalidName(String::from(name)));
+    }
+
+    let theme_dir = theme_dir(fs, dir, name);
+    if fs.exists(&theme_dir) {
+        return Err(Error::ThemeExists(String::from(name)));
+    }
+
+    let options = toml::to_string(options).context("Could not serialize theme options")?;
+    fs.create_dir_all(&theme_dir)
+        .context("Could not create theme directory")?;
+    let res = write_theme_files(fs, &theme_dir, &options, units);
+    if res.is_err() {
+        if let Err(e) = fs.remove_dir_all(&theme_dir) {
+            warn!("Could not remove {:?}: {}", theme_dir, e);
+        }
+    }
+
+    res.map(|_| theme_dir)
+}
+
+fn write_theme_files(
+    fs: &dyn Fs,
+    theme_dir: &Path,
+    options: &str,
+    units: &[(String, String)],
+) -> Result<(), Error> {
+    fs.write(&theme_dir.join("theme.toml"), options.as_bytes())
+        .context("Could not write theme.toml")?;
+    for (unit_name, data) in units {
+        let path = theme_dir.join(format!("unit-{}.toml", unit_name));
+        fs.write(&path, data.as_bytes())
+            .with_context(|| format!("Could not write {:?}", path))?;
+    }
+
+    Ok(())
+}
+
+/// Compound unit files for every unit of the theme
+pub fn copied_units(source: &ThemeDesc) -> Result<Vec<(String, String)>, Error> {
+    source
+        .units
+        .iter()
+        .map(|(unit_name, unit)| Ok((unit_name.clone(), unit_data(&unit.values)?)))
+        .collect()
+}
+
+/// Serializes the values of a unit as a compound unit file
+pub fn unit_data(values: &HashMap<String, String>) -> Result<String, Error> {
+    let values = values.iter().collect::<BTreeMap<_, _>>();
+    toml::to_string(&values).context("Could not serialize unit")
+}
+
+/// Compound unit files for every template, listing the values it references.
+///
+/// The values are commented out and set to whatever the theme chain currently resolves them to.
+pub fn stub_units(
+    fs: &dyn Fs,
+    install: &InstallDesc,
+    theme_chain: &[&ThemeDesc],
+) -> Result<Vec<(String, String)>, Error> {
+    let mut units = Vec::new();
+    for file in install.files.iter().filter(|file| file.template) {
+        let path = install.resolve_theme_chain_path(fs, theme_chain, file);
+        let template = fs
+            .read_to_string(&path)
+            .with_context(|| format!("Could not read template {:?}", path))?;
+
+        let values = file.values(theme_chain);
+        let mut data = String::new();
+        for variable in utils::template_variables(&template) {
+            let value = values.get(variable.as_str()).copied().unwrap_or("");
+            let value = toml::Value::String(String::from(value));
+            writeln!(data, "# {} = {}", variable, value).unwrap();
+        }
+
+        units.push((file.name.clone(), data));
+    }
+
+    Ok(units)
+}
@@ -0,0 +1,201 @@
+use std::{convert::TryFrom, f64::consts::PI};
+
+use chrono::{DateTime, Duration, Local, NaiveDate, NaiveTime, TimeZone, Utc};
+use serde::Deserialize;
+
+use crate::prelude::*;
+
+/// When a schedule entry becomes active each day
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum At {
+    Time(NaiveTime),
+    Sunrise,
+    Sunset,
+}
+
+impl TryFrom<String> for At {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        match value.as_str() {
+            "sunrise" => Ok(At::Sunrise),
+            "sunset" => Ok(At::Sunset),
+            time => NaiveTime::parse_from_str(time, "%H:%M")
+                .map(At::Time)
+                .map_err(|_| {
+                    Error::InvalidSetting(format!(
+                        "'{}' is not a time of the form HH:MM, sunrise or sunset",
+                        time
+                    ))
+                }),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScheduleEntry {
+    pub at: At,
+    pub theme: String,
+}
+
+/// Where to compute sunrise and sunset for, in degrees north and east
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Sunrise and sunset in UTC on the given date, or `None` during polar day or night.
+///
+/// Uses the sunrise equation, which is accurate to a few minutes.
+pub fn sun_times(date: NaiveDate, location: Location) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let to_rad = PI / 180.0;
+    let noon = Utc.from_utc_datetime(&date.and_hms_opt(12, 0, 0)?);
+    let julian_day = noon.timestamp() as f64 / 86400.0 + 2440587.5;
+
+    let n = (julian_day - 2451545.0 + 0.0008).round();
+    let mean_noon = n - location.longitude / 360.0;
+    let anomaly = (357.5291 + 0.98560028 * mean_noon).rem_euclid(360.0) * to_rad;
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let longitude = (anomaly / to_rad + center + 180.0 + 102.9372).rem_euclid(360.0) * to_rad;
+    let transit = 2451545.0 + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * longitude).sin();
+
+    let declination = (longitude.sin() * (23.4397 * to_rad).sin()).asin();
+    let latitude = location.latitude * to_rad;
+    let cos_hour_angle = ((-0.833 * to_rad).sin() - latitude.sin() * declination.sin())
+        / (latitude.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let hour_angle = cos_hour_angle.acos() / to_rad;
+
+    let to_utc = |julian: f64| {
+        let secs = (julian - 2440587.5) * 86400.0;
+        Utc.timestamp_opt(secs.round() as i64, 0).single()
+    };
+    Some((
+        to_utc(transit - hour_angle / 360.0)?,
+        to_utc(transit + hour_angle / 360.0)?,
+    ))
+}
+
+/// When the entry becomes active on the given date
+fn start_on(
+    at: At,
+    date: NaiveDate,
+    location: Option<Location>,
+) -> Result<Option<DateTime<Local>>, Error> {
+    let sun_times = || {
+        let location = location.ok_or_else(|| {
+            Error::InvalidSetting(String::from(
+                "Scheduling by sunrise or sunset needs a [location]",
+            ))
+        })?;
+        Ok::<_, Error>(sun_times(date, location))
+    };
+
+    Ok(match at {
+        At::Time(time) => Local.from_local_datetime(&date.and_time(time)).earliest(),
+        At::Sunrise => sun_times()?.map(|(sunrise, _)| sunrise.with_timezone(&Local)),
+        At::Sunset => sun_times()?.map(|(_, sunset)| sunset.with_timezone(&Local)),
+    })
+}
+
+/// The theme of the entry that most recently became active
+pub fn active_theme(
+    schedule: &[ScheduleEntry],
+    location: Option<Location>,
+    now: DateTime<Local>,
+) -> Result<Option<&str>, Error> {
+    let today = now.date_naive();
+    let mut active: Option<(DateTime<Local>, &str)> = None;
+    for date in [today - Duration::days(1), today] {
+        for entry in schedule {
+            let start = match start_on(entry.at, date, location)? {
+                Some(start) if start <= now => start,
+                _ => continue,
+            };
+            if active.is_none_or(|(latest, _)| start >= latest) {
+                active = Some((start, &entry.theme));
+            }
+        }
+    }
+
+    Ok(active.map(|(_, theme)| theme))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn minutes(time: DateTime<Utc>) -> u32 {
+        use chrono::Timelike;
+        time.hour() * 60 + time.minute()
+    }
+
+    #[test]
+    fn berlin_midsummer() {
+        let berlin = Location {
+            latitude: 52.52,
+            longitude: 13.405,
+        };
+        let (sunrise, sunset) =
+            sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), berlin).unwrap();
+
+        // 02:43 and 19:33 UTC
+        assert!((minutes(sunrise) as i32 - 163).abs() <= 5);
+        assert!((minutes(sunset) as i32 - 1173).abs() <= 5);
+    }
+
+    #[test]
+    fn polar_day() {
+        let svalbard = Location {
+            latitude: 78.2,
+            longitude: 15.6,
+        };
+        assert!(sun_times(NaiveDate::from_ymd_opt(2024, 6, 21).unwrap(), svalbard).is_none());
+    }
+
+    #[test]
+    fn active_wraps_to_previous_day() {
+        let entry = |at: &str, theme: &str| ScheduleEntry {
+            at: At::try_from(String::from(at)).unwrap(),
+            theme: String::from(theme),
+        };
+        let schedule = [entry("07:00", "light"), entry("19:00", "dark")];
+        let at = |h, m| {
+            Local
+                .from_local_datetime(
+                    &NaiveDate::from_ymd_opt(2024, 3, 10)
+                        .unwrap()
+                        .and_hms_opt(h, m, 0)
+                        .unwrap(),
+                )
+                .unwrap()
+        };
+
+        assert_eq!(
+            active_theme(&schedule, None, at(12, 0)).unwrap(),
+            Some("light")
+        );
+        assert_eq!(
+            active_theme(&schedule, None, at(19, 0)).unwrap(),
+            Some("dark")
+        );
+        assert_eq!(
+            active_theme(&schedule, None, at(3, 0)).unwrap(),
+            Some("dark")
+        );
+        assert_eq!(active_theme(&[], None, at(3, 0)).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_time() {
+        assert!(At::try_from(String::from("25:00")).is_err());
+        assert!(At::try_from(String::from("noon")).is_err());
+    }
+}
@@ -1,8 +1,12 @@
 use std::collections::{hash_map, HashMap};
 use std::path::{Path, PathBuf};
 
-use crate::hooks::{HookLauncher, HookSet};
+use crate::check::Report;
+use crate::config::HookConfig;
+use crate::fs::Fs;
+use crate::hooks::{HookLauncher, HookSet, InlineHooks};
 use crate::prelude::*;
+use crate::reload::Reload;
 use utils::tree_reader::{TreeReader, TreeReaderNode};
 
 use regex::Regex;
@@ -12,32 +16,102 @@ pub struct UnitDesc {
     pub values: HashMap<String, String>,
 }
 
-#[derive(Debug, Default, serde::Deserialize)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct ThemeOptions {
-    #[serde(default)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     pub inherits: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub variant: Option<Variant>,
+    /// The same theme in the other variant, installed by `toggle`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub counterpart: Option<String>,
+    /// Groups of themes to `cycle` through
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Built-in actions run after installing the theme
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reload: Vec<Reload>,
+    /// Hooks declared inline, merged with the hook executables of the theme
+    #[serde(default, skip_serializing_if = "InlineHooks::is_empty")]
+    pub hooks: InlineHooks,
+}
+
+impl ThemeOptions {
+    /// Layers the options of the same theme from a later manager directory on top of these,
+    /// keeping the ones it doesn't set
+    fn merge(&mut self, other: ThemeOptions) {
+        fn merge_vec<T>(this: &mut Vec<T>, other: Vec<T>) {
+            if !other.is_empty() {
+                *this = other;
+            }
+        }
+
+        self.inherits = other.inherits.or(self.inherits.take());
+        self.description = other.description.or(self.description.take());
+        self.author = other.author.or(self.author.take());
+        self.variant = other.variant.or(self.variant);
+        self.counterpart = other.counterpart.or(self.counterpart.take());
+        merge_vec(&mut self.tags, other.tags);
+        merge_vec(&mut self.reload, other.reload);
+        self.hooks.extend(other.hooks);
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Variant {
+    Light,
+    Dark,
 }
 
 #[derive(Debug, Default)]
 pub struct ThemeDesc {
     pub name: String,
+    /// Directory of the theme in the manager directory with the highest priority
     pub dir: PathBuf,
+    /// Directories of the theme in earlier manager directories, which it still takes files from
+    pub layer_dirs: Vec<PathBuf>,
     pub hooks: HookSet,
     pub units: HashMap<String, UnitDesc>,
     pub options: ThemeOptions,
 }
 
 impl ThemeDesc {
-    pub fn get_hook_launcher(&self) -> HookLauncher {
+    /// Every directory of the theme, starting from the earliest layer
+    pub fn dirs(&self) -> impl DoubleEndedIterator<Item = &PathBuf> {
+        self.layer_dirs.iter().chain(std::iter::once(&self.dir))
+    }
+
+    /// Layers the same theme from a later manager directory on top of this one, unit by unit
+    /// and value by value
+    fn merge(&mut self, other: ThemeDesc) {
+        for (unit_name, unit) in other.units {
+            ensure_contains(&mut self.units, unit_name)
+                .values
+                .extend(unit.values);
+        }
+        self.hooks.extend(other.hooks);
+        self.options.merge(other.options);
+        self.layer_dirs.extend(other.layer_dirs);
+        self.layer_dirs
+            .push(std::mem::replace(&mut self.dir, other.dir));
+    }
+
+    pub fn get_hook_launcher<'a>(&'a self, config: &'a HookConfig) -> HookLauncher<'a> {
         HookLauncher::HookSet {
             theme_dir: &self.dir,
             theme_name: &self.name,
             hooks: &self.hooks,
+            config,
         }
     }
 }
 
-pub fn read_from(dir: &Path) -> HashMap<String, ThemeDesc> {
+pub fn read_from(fs: &dyn Fs, dir: &Path, report: &mut Report) -> HashMap<String, ThemeDesc> {
     trace!("Reading themes from {:?}", dir);
 
     let mut themes = HashMap::<String, ThemeDesc>::new();
@@ -46,51 +120,172 @@ pub fn read_from(dir: &Path) -> HashMap<String, ThemeDesc> {
         TreeReaderNode::Literal(String::from("theme")),
         TreeReaderNode::AnyDir,
     ];
-    for mut entry in TreeReader::new(dir, themes_desc).get_dir_entries_recursive() {
+    for mut entry in TreeReader::new(fs, dir, themes_desc).get_dir_entries_recursive() {
         assert_eq!(entry.captures.0.len(), 1);
         let theme_name = entry.captures.0.pop().unwrap();
         trace!("Found theme '{}' in {:?}", theme_name, entry.path);
 
-        let mut theme = ThemeDesc::default();
-        theme.name = theme_name.clone();
-        theme.dir = entry.path;
+        let mut theme = ThemeDesc {
+            name: theme_name.clone(),
+            dir: entry.path,
+            ..Default::default()
+        };
 
         let options_path = theme.dir.join("theme.toml");
-        let options = match std::fs::read_to_string(&options_path) {
-            Ok(options) => Some(options),
-
-            Err(e) => {
-                if options_path.exists() {
-                    error!("Could not read options file ({}): skipping theme", e);
+        if fs.exists(&options_path) {
+            match read_options_file(fs, &options_path) {
+                Ok(mut options) => {
+                    options.hooks.set_dir(&theme.dir);
+                    theme.options = options;
+                }
+                Err(e) => {
+                    report.error_from(options_path, &e.context("Skipping theme"));
                     continue;
                 }
-                None
             }
-        };
+        }
+
+        *ensure_contains(&mut themes, theme_name) = theme;
+    }
 
-        if let Some(options) = options {
-            let options = match toml::from_str(&options) {
-                Ok(options) => options,
+    read_units(fs, dir, &mut themes, report);
+    read_hooks(fs, dir, &mut themes, report);
+    for theme in themes.values_mut() {
+        let inline = theme.options.hooks.clone();
+        theme.hooks.add_inline(&inline);
+    }
 
-                Err(e) => {
-                    error!("Could not parse options file ({})", e);
-                    continue;
+    themes
+}
+
+/// Reads themes from every layer, merging themes with the same name so that later layers only
+/// need to contain what they change
+pub fn read_layers(
+    fs: &dyn Fs,
+    dirs: &[PathBuf],
+    report: &mut Report,
+) -> HashMap<String, ThemeDesc> {
+    let mut themes = HashMap::<String, ThemeDesc>::new();
+    for dir in dirs {
+        for (name, theme) in read_from(fs, dir, report) {
+            match themes.entry(name) {
+                hash_map::Entry::Occupied(mut entry) => {
+                    trace!(
+                        "Theme '{}' from {:?} is extended by {:?}",
+                        theme.name,
+                        entry.get().dir,
+                        theme.dir
+                    );
+                    entry.get_mut().merge(theme);
+                }
+                hash_map::Entry::Vacant(entry) => {
+                    entry.insert(theme);
                 }
-            };
+            }
+        }
+    }
+
+    themes
+}
 
-            theme.options = options;
+/// Returns the theme and everything it inherits, starting from the base theme
+pub fn resolve_chain<'a>(
+    themes: &'a HashMap<String, ThemeDesc>,
+    name: &str,
+) -> Result<Vec<&'a ThemeDesc>, Error> {
+    let mut chain = Vec::<&ThemeDesc>::new();
+    let mut theme = themes
+        .get(name)
+        .ok_or_else(|| Error::NoTheme(String::from(name)))?;
+
+    loop {
+        if chain.iter().any(|prev| prev.name == theme.name) {
+            let cycle = chain
+                .iter()
+                .map(|theme| theme.name.as_str())
+                .chain(std::iter::once(theme.name.as_str()))
+                .collect::<Vec<_>>()
+                .join(" -> ");
+            return Err(Error::InheritCycle(cycle));
+        }
+        chain.push(theme);
+
+        match theme.options.inherits {
+            Some(ref inherits) => {
+                theme = themes
+                    .get(inherits)
+                    .ok_or_else(|| Error::NoTheme(inherits.clone()))
+                    .with_context(|| format!("Theme '{}' inherits a missing theme", theme.name))?;
+            }
+            None => break,
         }
+    }
 
-        *ensure_contains(&mut themes, theme_name) = theme;
+    chain.reverse();
+    Ok(chain)
+}
+
+/// The theme `toggle` switches to, either declared by the theme itself or by a theme naming it
+/// as its counterpart
+pub fn counterpart<'a>(
+    themes: &'a HashMap<String, ThemeDesc>,
+    name: &str,
+) -> Option<&'a ThemeDesc> {
+    match themes.get(name)?.options.counterpart {
+        Some(ref counterpart) => themes.get(counterpart),
+        None => {
+            let mut candidates = themes
+                .values()
+                .filter(|theme| theme.options.counterpart.as_deref() == Some(name))
+                .collect::<Vec<_>>();
+            candidates.sort_by(|a, b| a.name.cmp(&b.name));
+            candidates.into_iter().next()
+        }
     }
+}
 
-    read_units(dir, &mut themes);
-    read_hooks(dir, &mut themes);
+/// Whether the theme only exists to be inherited from: other themes inherit it and it declares
+/// neither tags nor a variant
+fn is_abstract(themes: &HashMap<String, ThemeDesc>, theme: &ThemeDesc) -> bool {
+    theme.options.tags.is_empty()
+        && theme.options.variant.is_none()
+        && themes
+            .values()
+            .any(|other| other.options.inherits.as_deref() == Some(theme.name.as_str()))
+}
 
-    themes
+/// The theme after `current` in alphabetical order among the ones with the tag, wrapping around.
+///
+/// The default theme is never part of the cycle, and neither are abstract themes unless a tag
+/// selects them.
+pub fn cycle_next<'a>(
+    themes: &'a HashMap<String, ThemeDesc>,
+    default_theme: &str,
+    current: Option<&str>,
+    tag: Option<&str>,
+) -> Option<&'a ThemeDesc> {
+    let mut cycle = themes
+        .values()
+        .filter(|theme| theme.name != default_theme)
+        .filter(|theme| match tag {
+            Some(tag) => theme.options.tags.iter().any(|t| t == tag),
+            None => !is_abstract(themes, theme),
+        })
+        .collect::<Vec<_>>();
+    cycle.sort_by(|a, b| a.name.cmp(&b.name));
+
+    let next = current
+        .and_then(|current| cycle.iter().position(|theme| theme.name == current))
+        .map_or(0, |i| i + 1);
+    cycle.get(next).or_else(|| cycle.first()).copied()
 }
 
-fn read_units(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
+fn read_units(
+    fs: &dyn Fs,
+    dir: &Path,
+    themes: &mut HashMap<String, ThemeDesc>,
+    report: &mut Report,
+) {
     let unit_values_desc = &[
         TreeReaderNode::Literal(String::from("theme")),
         TreeReaderNode::AnyDir,
@@ -98,7 +293,7 @@ fn read_units(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
         TreeReaderNode::Any,
         TreeReaderNode::Any,
     ];
-    for entry in TreeReader::new(dir, unit_values_desc).get_file_entries_recursive() {
+    for entry in TreeReader::new(fs, dir, unit_values_desc).get_file_entries_recursive() {
         assert_eq!(entry.captures.0.len(), 3);
         let mut captures = entry.captures.0;
 
@@ -113,17 +308,18 @@ fn read_units(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
             theme_name
         );
 
-        let theme = themes
-            .get_mut(&theme_name)
-            .expect("Found unit belonging to a nonexistent theme. This is probably a bug.");
+        // The theme was skipped, which has been reported already
+        let Some(theme) = themes.get_mut(&theme_name) else {
+            continue;
+        };
         let unit = ensure_contains(&mut theme.units, unit_name);
 
-        match read_value_file(&entry.path) {
+        match read_value_file(fs, &entry.path) {
             Ok(value) => {
                 unit.values.insert(value_name, value);
             }
             Err(e) => {
-                error!("Could not read value file {:?}: {}", entry.path, e);
+                report.error_from(entry.path, &e.context("Could not read value file"));
                 continue;
             }
         }
@@ -135,7 +331,7 @@ fn read_units(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
         TreeReaderNode::Literal(String::from("unit")),
         TreeReaderNode::Pattern(Regex::new("^(.*)\\.toml$").unwrap()),
     ];
-    for entry in TreeReader::new(dir, units_compound_desc).get_file_entries_recursive() {
+    for entry in TreeReader::new(fs, dir, units_compound_desc).get_file_entries_recursive() {
         assert_eq!(entry.captures.0.len(), 2);
         let mut captures = entry.captures.0;
 
@@ -148,24 +344,30 @@ fn read_units(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
             theme_name
         );
 
-        let theme = themes
-            .get_mut(&theme_name)
-            .expect("Found unit belonging to a nonexistent theme. This is probably a bug.");
+        // The theme was skipped, which has been reported already
+        let Some(theme) = themes.get_mut(&theme_name) else {
+            continue;
+        };
         let unit = ensure_contains(&mut theme.units, unit_name);
 
-        match read_compound_file(&entry.path) {
+        match read_compound_file(fs, &entry.path) {
             Ok(values) => {
                 unit.values.extend(values);
             }
             Err(e) => {
-                error!("Could not read compound file {:?}: {}", entry.path, e);
+                report.error_from(entry.path, &e.context("Could not read compound file"));
                 continue;
             }
         }
     }
 }
 
-fn read_hooks(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
+fn read_hooks(
+    fs: &dyn Fs,
+    dir: &Path,
+    themes: &mut HashMap<String, ThemeDesc>,
+    report: &mut Report,
+) {
     let hooks_desc = &[
         TreeReaderNode::Literal(String::from("theme")),
         TreeReaderNode::AnyDir,
@@ -173,7 +375,7 @@ fn read_hooks(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
         TreeReaderNode::Any,
         TreeReaderNode::Any,
     ];
-    for entry in TreeReader::new(dir, hooks_desc).get_file_entries_recursive() {
+    for entry in TreeReader::new(fs, dir, hooks_desc).get_file_entries_recursive() {
         assert_eq!(entry.captures.0.len(), 3);
         let mut captures = entry.captures.0;
 
@@ -188,37 +390,229 @@ fn read_hooks(dir: &Path, themes: &mut HashMap<String, ThemeDesc>) {
             theme_name
         );
 
-        let theme = themes
-            .get_mut(&theme_name)
-            .expect("Found hook belonging to a nonexistent theme. This is probably a bug.");
+        // The theme was skipped, which has been reported already
+        let Some(theme) = themes.get_mut(&theme_name) else {
+            continue;
+        };
         match hook_set_name.as_str() {
             "preinstall" => theme.hooks.preinstall.add(hook_name, entry.path),
             "postinstall" => theme.hooks.postinstall.add(hook_name, entry.path),
             "preremove" => theme.hooks.preremove.add(hook_name, entry.path),
             "postremove" => theme.hooks.postremove.add(hook_name, entry.path),
-            _ => warn!(
-                "Hook set '{}' is invalid. Hook will be ignored",
-                hook_set_name
+            "onchange" => theme.hooks.onchange.add(hook_name, entry.path),
+            _ => report.warning(
+                entry.path,
+                format!(
+                    "Hook set '{}' is invalid. Hook will be ignored",
+                    hook_set_name
+                ),
             ),
         }
     }
 }
 
-fn read_value_file(path: &Path) -> Result<String, Error> {
-    Ok(std::fs::read_to_string(path)?)
+fn read_options_file(fs: &dyn Fs, path: &Path) -> Result<ThemeOptions, Error> {
+    let data = fs
+        .read_to_string(path)
+        .context("Could not read options file")?;
+    let options = toml::de::from_str(&data).context("Could not parse options file")?;
+
+    Ok(options)
+}
+
+fn read_value_file(fs: &dyn Fs, path: &Path) -> Result<String, Error> {
+    Ok(fs.read_to_string(path)?)
 }
 
-fn read_compound_file(path: &Path) -> Result<HashMap<String, String>, Error> {
-    let data = read_value_file(path)?;
+fn read_compound_file(fs: &dyn Fs, path: &Path) -> Result<HashMap<String, String>, Error> {
+    let data = read_value_file(fs, path)?;
     let values: HashMap<String, String> =
         toml::de::from_str::<HashMap<String, String>>(&data).context("Format error")?;
 
     Ok(values)
 }
 
-fn ensure_contains<'a, T: Default>(map: &'a mut HashMap<String, T>, key: String) -> &'a mut T {
+fn ensure_contains<T: Default>(map: &mut HashMap<String, T>, key: String) -> &mut T {
     match map.entry(key) {
         hash_map::Entry::Vacant(entry) => entry.insert(T::default()),
         hash_map::Entry::Occupied(entry) => entry.into_mut(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemoryFs;
+
+    fn theme(name: &str, inherits: Option<&str>) -> (String, ThemeDesc) {
+        let theme = ThemeDesc {
+            name: String::from(name),
+            options: ThemeOptions {
+                inherits: inherits.map(String::from),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        (String::from(name), theme)
+    }
+
+    fn tagged(name: &str, tags: &[&str], counterpart: Option<&str>) -> (String, ThemeDesc) {
+        let (name, mut theme) = theme(name, None);
+        theme.options.tags = tags.iter().map(|tag| String::from(*tag)).collect();
+        theme.options.counterpart = counterpart.map(String::from);
+        (name, theme)
+    }
+
+    fn chain_names(chain: &[&ThemeDesc]) -> Vec<String> {
+        chain.iter().map(|theme| theme.name.clone()).collect()
+    }
+
+    #[test]
+    fn resolve_chain_order() {
+        let themes = vec![
+            theme("base", None),
+            theme("dark", Some("base")),
+            theme("darker", Some("dark")),
+        ]
+        .into_iter()
+        .collect();
+
+        let chain = resolve_chain(&themes, "darker").unwrap();
+        assert_eq!(chain_names(&chain), &["base", "dark", "darker"]);
+    }
+
+    #[test]
+    fn resolve_chain_missing() {
+        let themes = vec![theme("dark", Some("base"))].into_iter().collect();
+
+        assert!(resolve_chain(&themes, "light").is_err());
+        assert!(resolve_chain(&themes, "dark").is_err());
+    }
+
+    #[test]
+    fn resolve_chain_cycle() {
+        let themes = vec![theme("a", Some("b")), theme("b", Some("a"))]
+            .into_iter()
+            .collect();
+
+        match resolve_chain(&themes, "a") {
+            Err(Error::InheritCycle(cycle)) => assert_eq!(cycle, "a -> b -> a"),
+            other => panic!("unexpected result: {:?}", other.map(|c| chain_names(&c))),
+        }
+    }
+
+    #[test]
+    fn counterpart_either_way() {
+        let themes = vec![
+            tagged("day", &[], Some("night")),
+            tagged("night", &[], None),
+            tagged("plain", &[], None),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(counterpart(&themes, "day").unwrap().name, "night");
+        assert_eq!(counterpart(&themes, "night").unwrap().name, "day");
+        assert!(counterpart(&themes, "plain").is_none());
+    }
+
+    #[test]
+    fn cycle_wraps_within_tag() {
+        let themes = vec![
+            tagged("a", &["dark"], None),
+            tagged("b", &[], None),
+            tagged("c", &["dark"], None),
+        ]
+        .into_iter()
+        .collect();
+
+        let next =
+            |current, tag| cycle_next(&themes, "default", current, tag).map(|t| t.name.as_str());
+        assert_eq!(next(Some("a"), Some("dark")), Some("c"));
+        assert_eq!(next(Some("c"), Some("dark")), Some("a"));
+        assert_eq!(next(Some("b"), Some("dark")), Some("a"));
+        assert_eq!(next(Some("a"), None), Some("b"));
+        assert_eq!(next(None, Some("light")), None);
+    }
+
+    #[test]
+    fn cycle_skips_default_and_abstract_themes() {
+        let mut themes: HashMap<_, _> = vec![
+            theme("default", None),
+            theme("base", None),
+            theme("dark", Some("base")),
+            theme("light", Some("base")),
+            theme("dim", Some("dark")),
+        ]
+        .into_iter()
+        .collect();
+
+        let names = |themes: &HashMap<String, ThemeDesc>| {
+            let mut names = Vec::new();
+            let mut current = None;
+            while let Some(next) = cycle_next(themes, "default", current, None) {
+                if names.contains(&next.name) {
+                    break;
+                }
+                names.push(next.name.clone());
+                current = Some(next.name.as_str());
+            }
+            names
+        };
+        assert_eq!(names(&themes), vec!["dim", "light"]);
+
+        themes.get_mut("dark").unwrap().options.variant = Some(Variant::Dark);
+        assert_eq!(names(&themes), vec!["dark", "dim", "light"]);
+    }
+
+    #[test]
+    fn broken_theme_is_skipped_with_its_units() {
+        let fs = MemoryFs::with_files([
+            ("/m/theme-dark/theme.toml", "inherits = "),
+            ("/m/theme-dark/unit-a.toml", "fg = \"red\""),
+            ("/m/theme-dark/hook-postinstall-reload", "true"),
+            ("/m/theme-light/unit-a.toml", "fg = \"black\""),
+        ]);
+        let mut report = Report::default();
+
+        let themes = read_from(&fs, Path::new("/m"), &mut report);
+        assert_eq!(themes.keys().collect::<Vec<_>>(), vec!["light"]);
+        assert_eq!(report.num_errors(), 1);
+    }
+
+    #[test]
+    fn layers_merge_units() {
+        let fs = MemoryFs::with_files([
+            ("/shared/theme-dark/theme.toml", "variant = \"dark\""),
+            (
+                "/shared/theme-dark/unit-colors.toml",
+                "fg = \"white\"\nbg = \"black\"",
+            ),
+            ("/shared/theme-dark/unit-font.toml", "size = \"11\""),
+            ("/user/theme-dark/theme.toml", "tags = [\"mine\"]"),
+            ("/user/theme-dark/unit-colors.toml", "bg = \"gray\""),
+        ]);
+        let mut report = Report::default();
+
+        let themes = read_layers(
+            &fs,
+            &[PathBuf::from("/shared"), PathBuf::from("/user")],
+            &mut report,
+        );
+        let dark = &themes["dark"];
+        let value = |unit: &str, value: &str| dark.units[unit].values[value].as_str();
+        assert_eq!(value("colors", "fg"), "white");
+        assert_eq!(value("colors", "bg"), "gray");
+        assert_eq!(value("font", "size"), "11");
+        assert_eq!(dark.options.variant, Some(Variant::Dark));
+        assert_eq!(dark.options.tags, ["mine"]);
+        assert_eq!(
+            dark.dirs().collect::<Vec<_>>(),
+            [
+                Path::new("/shared/theme-dark"),
+                Path::new("/user/theme-dark")
+            ]
+        );
+    }
+}
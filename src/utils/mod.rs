@@ -1,5 +1,8 @@
 pub mod read_dir;
+pub mod template;
 pub mod tree_reader;
+pub mod xdg;
 
 pub use read_dir::*;
+pub use template::*;
 pub use tree_reader::*;
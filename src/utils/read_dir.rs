@@ -1,5 +1,7 @@
 use std::path::{Path, PathBuf};
 
+use crate::fs::{DirEntry, Fs};
+
 #[derive(Debug, Clone, Copy)]
 pub enum ReadDirOptions {
     Files,
@@ -8,10 +10,10 @@ pub enum ReadDirOptions {
 }
 
 impl ReadDirOptions {
-    fn filter(&self, metadata: &std::fs::Metadata) -> bool {
+    fn filter(&self, entry: &DirEntry) -> bool {
         match self {
-            ReadDirOptions::Files => metadata.is_file(),
-            ReadDirOptions::Directories => metadata.is_dir(),
+            ReadDirOptions::Files => !entry.is_dir,
+            ReadDirOptions::Directories => entry.is_dir,
             ReadDirOptions::Both => true,
         }
     }
@@ -40,24 +42,24 @@ pub struct ReadDirEntry {
 }
 
 pub fn read_dir(
+    fs: &dyn Fs,
     dir: &Path,
     options: ReadDirOptions,
 ) -> Result<impl Iterator<Item = ReadDirEntry>, std::io::Error> {
-    let iter = std::fs::read_dir(dir)?
+    let iter = fs
+        .read_dir(dir)?
+        .into_iter()
+        .filter(move |entry| options.filter(entry))
         .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let metadata = std::fs::metadata(&entry.path()).ok()?;
-            Some((entry, metadata))
-        })
-        .filter(move |(_, metadata)| options.filter(&metadata))
-        .map(|(entry, metadata)| ReadDirEntry {
-            file_name: entry.file_name().to_str().unwrap().to_owned(),
-            path: entry.path(),
-            entry_type: if metadata.is_file() {
-                ReadDirEntryType::File
-            } else {
-                ReadDirEntryType::Directory
-            },
+            Some(ReadDirEntry {
+                file_name: entry.path.file_name()?.to_str()?.to_owned(),
+                entry_type: if entry.is_dir {
+                    ReadDirEntryType::Directory
+                } else {
+                    ReadDirEntryType::File
+                },
+                path: entry.path,
+            })
         });
 
     Ok(iter)
@@ -0,0 +1,71 @@
+use std::collections::BTreeSet;
+
+/// Returns the names of all values a mustache template references.
+///
+/// Only the first part of dotted names is returned, since that is what units provide.
+/// Comments, partials and closing tags are skipped, and scanning stops at a delimiter change.
+pub fn template_variables(template: &str) -> BTreeSet<String> {
+    let mut variables = BTreeSet::new();
+
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rest = &rest[start + 2..];
+
+        let (tag, close) = if let Some(tag) = rest.strip_prefix('{') {
+            (tag, "}}}")
+        } else {
+            (rest, "}}")
+        };
+        let end = match tag.find(close) {
+            Some(end) => end,
+            None => break,
+        };
+        let name = tag[..end].trim();
+        rest = &tag[end + close.len()..];
+
+        let name = match name.chars().next() {
+            Some('!') | Some('>') | Some('/') => continue,
+            Some('=') => break,
+            Some('#') | Some('^') | Some('&') => name[1..].trim(),
+            _ => name,
+        };
+
+        if let Some(name) = name.split('.').next() {
+            if !name.is_empty() {
+                variables.insert(String::from(name));
+            }
+        }
+    }
+
+    variables
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(template: &str) -> Vec<String> {
+        template_variables(template).into_iter().collect()
+    }
+
+    #[test]
+    fn plain_tags() {
+        assert_eq!(vars("bg {{bg}}\nfg {{ fg }}\n"), &["bg", "fg"]);
+        assert_eq!(vars("{{{raw}}} {{&also_raw}}"), &["also_raw", "raw"]);
+    }
+
+    #[test]
+    fn sections() {
+        assert_eq!(
+            vars("{{#dark}}{{color.bg}}{{/dark}}{{^dark}}light{{/dark}}"),
+            &["color", "dark"]
+        );
+    }
+
+    #[test]
+    fn skipped_tags() {
+        assert!(vars("{{! comment }}{{> partial}}{{.}}").is_empty());
+        assert_eq!(vars("{{a}}{{=<% %>=}}<% b %>"), &["a"]);
+        assert_eq!(vars("{{a}} {{unclosed"), &["a"]);
+    }
+}
@@ -1,5 +1,6 @@
 use std::path::{Path, PathBuf};
 
+use crate::fs::Fs;
 use crate::prelude::*;
 
 #[derive(Debug)]
@@ -12,15 +13,16 @@ pub enum TreeReaderNode {
 
 #[derive(Debug)]
 pub struct TreeReader<'a> {
+    fs: &'a dyn Fs,
     dir: &'a Path,
     desc: &'a [TreeReaderNode],
 }
 
 impl<'a> TreeReader<'a> {
-    pub fn new(dir: &'a Path, desc: &'a [TreeReaderNode]) -> Self {
-        assert!(desc.len() > 0);
+    pub fn new(fs: &'a dyn Fs, dir: &'a Path, desc: &'a [TreeReaderNode]) -> Self {
+        assert!(!desc.is_empty());
 
-        TreeReader { dir, desc }
+        TreeReader { fs, dir, desc }
     }
 
     pub fn get_file_entries_recursive(&self) -> Vec<TreeReaderEntry> {
@@ -75,7 +77,7 @@ impl<'a> TreeReader<'a> {
     }
 
     pub fn get_file_entries(&self) -> Vec<TreeReaderEntry> {
-        utils::read_dir(self.dir, utils::ReadDirOptions::Files)
+        utils::read_dir(self.fs, self.dir, utils::ReadDirOptions::Files)
             .map(|read_dir| {
                 read_dir
                     .filter_map(|entry| {
@@ -92,7 +94,7 @@ impl<'a> TreeReader<'a> {
     }
 
     pub fn get_dir_entries(&self) -> Vec<TreeReaderEntry> {
-        utils::read_dir(self.dir, utils::ReadDirOptions::Directories)
+        utils::read_dir(self.fs, self.dir, utils::ReadDirOptions::Directories)
             .map(|read_dir| {
                 read_dir
                     .filter_map(|entry| {
@@ -110,6 +112,7 @@ impl<'a> TreeReader<'a> {
 
     fn step_down(&self, dir: &'a Path) -> Option<Self> {
         Some(TreeReader {
+            fs: self.fs,
             dir,
             desc: self
                 .desc
@@ -248,7 +251,7 @@ fn match_dir_name(desc: &[TreeReaderNode], name: &str) -> Option<Captures> {
     Some(Captures(captures))
 }
 
-fn make_plural(s: impl Into<String>) -> String {
+pub fn make_plural(s: impl Into<String>) -> String {
     s.into() + "s"
 }
 
@@ -0,0 +1,18 @@
+use std::path::PathBuf;
+
+fn base_dir(var: &str, fallback: &str) -> Option<PathBuf> {
+    std::env::var_os(var)
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(fallback)))
+}
+
+/// `$XDG_CONFIG_HOME/theme-manager`, the default manager directory
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config").map(|dir| dir.join("theme-manager"))
+}
+
+/// `$XDG_STATE_HOME/theme-manager`, where the installed theme and other state is kept
+pub fn state_dir() -> Option<PathBuf> {
+    base_dir("XDG_STATE_HOME", ".local/state").map(|dir| dir.join("theme-manager"))
+}
@@ -0,0 +1,320 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::config::ConfigFile;
+use crate::fs::RealFs;
+use crate::install::FileDesc;
+use crate::manager::ThemeManager;
+use crate::prelude::*;
+use utils::{make_plural, ReadDirOptions};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long nothing has to change before a batch of changes is handled
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// What a change to a file in a manager directory affects
+#[derive(Debug, PartialEq, Eq)]
+enum Change {
+    /// Settings, install.toml or theme options: reload everything and re-render every file
+    Everything,
+    /// Hooks were added or removed: reload without re-rendering
+    Hooks,
+    /// Values of a unit of a theme
+    Unit {
+        theme: String,
+        unit: String,
+    },
+    /// A file a theme provides in place of the one in the install directory
+    ThemeFile {
+        theme: String,
+        path: PathBuf,
+    },
+    /// A template or file in the install directory
+    Source(PathBuf),
+    Ignored,
+}
+
+/// Works out what a changed path relative to its manager directory affects
+fn classify(relative: &Path) -> Change {
+    let components = relative
+        .iter()
+        .filter_map(|component| component.to_str())
+        .collect::<Vec<_>>();
+    if components
+        .iter()
+        .any(|c| c.starts_with('.') || c.starts_with('_') || c.ends_with('~'))
+    {
+        return Change::Ignored;
+    }
+
+    match components.as_slice() {
+        ["config.toml"] | ["install", "install.toml"] => return Change::Everything,
+        ["install", rest @ ..] => return Change::Source(rest.iter().collect()),
+        _ => {}
+    }
+
+    // Directories can be collapsed into file names with dashes, like the tree reader allows
+    let parts = components
+        .iter()
+        .flat_map(|component| component.split('-'))
+        .collect::<Vec<_>>();
+    let keyword = |part: &str, word: &str| part == word || part == make_plural(word);
+
+    match parts.as_slice() {
+        [hook, ..] if keyword(hook, "hook") => Change::Hooks,
+        [theme, name, rest @ ..] if keyword(theme, "theme") => match rest {
+            ["theme.toml"] => Change::Everything,
+            [hook, ..] if keyword(hook, "hook") => Change::Hooks,
+            [unit, unit_name, _value] if keyword(unit, "unit") => Change::Unit {
+                theme: String::from(*name),
+                unit: String::from(*unit_name),
+            },
+            [unit, file] if keyword(unit, "unit") && file.ends_with(".toml") => Change::Unit {
+                theme: String::from(*name),
+                unit: String::from(file.trim_end_matches(".toml")),
+            },
+            _ => {
+                // Skip the components making up the theme directory itself
+                let mut consumed = 0;
+                let theme_dir_len = components
+                    .iter()
+                    .take_while(|component| {
+                        consumed += component.split('-').count();
+                        consumed <= 2
+                    })
+                    .count();
+                match components.get(theme_dir_len..) {
+                    Some(path) if !path.is_empty() && consumed >= 2 => Change::ThemeFile {
+                        theme: String::from(*name),
+                        path: path.iter().collect(),
+                    },
+                    _ => Change::Ignored,
+                }
+            }
+        },
+        _ => Change::Ignored,
+    }
+}
+
+/// Modification times of every file in the directories
+pub fn snapshot(dirs: &[PathBuf]) -> BTreeMap<PathBuf, SystemTime> {
+    fn walk(dir: &Path, files: &mut BTreeMap<PathBuf, SystemTime>) {
+        let entries = match utils::read_dir(&RealFs, dir, ReadDirOptions::Both) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries {
+            if entry.entry_type.is_dir() {
+                walk(&entry.path, files);
+            } else if let Ok(modified) = entry.path.metadata().and_then(|m| m.modified()) {
+                files.insert(entry.path, modified);
+            }
+        }
+    }
+
+    let mut files = BTreeMap::new();
+    for dir in dirs {
+        walk(dir, &mut files);
+    }
+
+    files
+}
+
+/// Paths that were added, removed or modified
+fn changed_paths(
+    old: &BTreeMap<PathBuf, SystemTime>,
+    new: &BTreeMap<PathBuf, SystemTime>,
+) -> BTreeSet<PathBuf> {
+    let removed = old.keys().filter(|path| !new.contains_key(*path));
+    let modified = new
+        .iter()
+        .filter(|(path, modified)| old.get(*path) != Some(modified))
+        .map(|(path, _)| path);
+
+    removed.chain(modified).cloned().collect()
+}
+
+/// Waits for changes and returns them once nothing has changed for a while
+pub fn next_batch(
+    dirs: &[PathBuf],
+    files: &mut BTreeMap<PathBuf, SystemTime>,
+) -> BTreeSet<PathBuf> {
+    let mut batch = BTreeSet::new();
+    loop {
+        std::thread::sleep(if batch.is_empty() {
+            POLL_INTERVAL
+        } else {
+            DEBOUNCE
+        });
+
+        let current = snapshot(dirs);
+        let changed = changed_paths(files, &current);
+        *files = current;
+        if changed.is_empty() && !batch.is_empty() {
+            return batch;
+        }
+        batch.extend(changed);
+    }
+}
+
+fn affects(changes: &[Change], theme_chain: &[&str], file: &FileDesc) -> bool {
+    changes.iter().any(|change| match change {
+        Change::Everything => true,
+        Change::Unit { theme, unit } => {
+            theme_chain.contains(&theme.as_str()) && file.uses_unit(unit)
+        }
+        Change::ThemeFile { theme, path } => {
+            theme_chain.contains(&theme.as_str()) && file.path == *path
+        }
+        Change::Source(path) => file.path == *path,
+        Change::Hooks | Change::Ignored => false,
+    })
+}
+
+/// Re-installs the files affected by changes to the manager directories until killed
+pub fn run(dirs: &[PathBuf], state_dir: &Path, overrides: ConfigFile) -> Result<(), Error> {
+    let mut manager = ThemeManager::read_from_dirs(dirs, state_dir, overrides.clone())?;
+    let mut files = snapshot(dirs);
+    println!("Watching {} directories", dirs.len());
+
+    loop {
+        let batch = next_batch(dirs, &mut files);
+        let changes = batch
+            .iter()
+            .filter_map(|path| {
+                let dir = dirs.iter().find(|dir| path.starts_with(dir))?;
+                Some(classify(path.strip_prefix(dir).ok()?))
+            })
+            .filter(|change| *change != Change::Ignored)
+            .collect::<Vec<_>>();
+        trace!("Changes: {:?}", changes);
+        if changes.is_empty() {
+            continue;
+        }
+
+        let reload = changes
+            .iter()
+            .any(|change| matches!(change, Change::Everything | Change::Hooks));
+        let reload_themes = changes
+            .iter()
+            .any(|change| matches!(change, Change::Unit { .. } | Change::ThemeFile { .. }));
+        if reload {
+            match ThemeManager::read_from_dirs(dirs, state_dir, overrides.clone()) {
+                Ok(reloaded) => manager = reloaded,
+                Err(e) => {
+                    error!("Could not reload: {}", e);
+                    continue;
+                }
+            }
+        } else if reload_themes {
+            manager.reload_themes();
+        }
+
+        let theme_name = match manager.installed_theme() {
+            Ok(Some(theme_name)) => theme_name,
+            Ok(None) => {
+                trace!("No theme installed, nothing to re-render");
+                continue;
+            }
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+        let theme_chain = match manager.theme_chain(&theme_name) {
+            Ok(theme_chain) => theme_chain
+                .iter()
+                .map(|theme| theme.name.as_str())
+                .collect::<Vec<_>>(),
+            Err(e) => {
+                error!("{}", e);
+                continue;
+            }
+        };
+
+        match manager.install_files(&theme_name, |file| affects(&changes, &theme_chain, file)) {
+            Ok(report) if report.changed.is_empty() => trace!("No files changed"),
+            Ok(report) => {
+                for target in &report.changed {
+                    println!("Changed {}", target.display());
+                }
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(theme: &str, unit: &str) -> Change {
+        Change::Unit {
+            theme: String::from(theme),
+            unit: String::from(unit),
+        }
+    }
+
+    #[test]
+    fn classify_paths() {
+        let classify = |path: &str| classify(Path::new(path));
+
+        assert_eq!(classify("config.toml"), Change::Everything);
+        assert_eq!(classify("install/install.toml"), Change::Everything);
+        assert_eq!(
+            classify("install/kitty/kitty.conf"),
+            Change::Source(PathBuf::from("kitty/kitty.conf"))
+        );
+        assert_eq!(classify("themes/dark/theme.toml"), Change::Everything);
+        assert_eq!(
+            classify("themes/dark/units/kitty/background"),
+            unit("dark", "kitty")
+        );
+        assert_eq!(
+            classify("themes/dark/units/kitty.toml"),
+            unit("dark", "kitty")
+        );
+        assert_eq!(
+            classify("theme-dark-unit-kitty-background"),
+            unit("dark", "kitty")
+        );
+        assert_eq!(
+            classify("themes/dark-units/kitty.toml"),
+            unit("dark", "kitty")
+        );
+        assert_eq!(classify("hooks/postinstall/reload"), Change::Hooks);
+        assert_eq!(
+            classify("themes/dark/hooks/postinstall/reload"),
+            Change::Hooks
+        );
+        assert_eq!(
+            classify("themes/dark/kitty/kitty.conf"),
+            Change::ThemeFile {
+                theme: String::from("dark"),
+                path: PathBuf::from("kitty/kitty.conf"),
+            }
+        );
+        assert_eq!(classify("themes/dark/.theme.toml.swp"), Change::Ignored);
+        assert_eq!(classify("_unpack123/theme.toml"), Change::Ignored);
+    }
+
+    #[test]
+    fn changed_paths_between_snapshots() {
+        let time = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let old = vec![(PathBuf::from("a"), time(1)), (PathBuf::from("b"), time(1))]
+            .into_iter()
+            .collect();
+        let new = vec![(PathBuf::from("b"), time(2)), (PathBuf::from("c"), time(1))]
+            .into_iter()
+            .collect();
+
+        let changed = changed_paths(&old, &new);
+        assert_eq!(
+            changed.into_iter().collect::<Vec<_>>(),
+            vec![PathBuf::from("a"), PathBuf::from("b"), PathBuf::from("c")]
+        );
+    }
+}